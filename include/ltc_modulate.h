@@ -0,0 +1,83 @@
+/*
+ * C interface to the Love-to-Code program modulator.
+ *
+ * Build the library with:
+ *
+ *     cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib
+ *
+ * and link against libltc_modulate.so, ltc_modulate.dll or
+ * libltc_modulate.dylib.
+ *
+ * An encoder starts out with the same settings as the command-line tool.
+ * Functions that return an int give LTC_OK on success, or one of the
+ * LTC_ERR_ codes below.  Passing a NULL encoder, or NULL for any other
+ * pointer that is required, gives LTC_ERR_INVALID_ARGUMENT.  A single
+ * encoder must not be used from two threads at once.
+ */
+
+#ifndef LTC_MODULATE_H
+#define LTC_MODULATE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LTC_OK                    0
+#define LTC_ERR_INVALID_ARGUMENT -1  /* NULL pointer or unrecognised value */
+#define LTC_ERR_INVALID_SETTINGS -2  /* e.g. a tone above the Nyquist rate */
+#define LTC_ERR_IO               -3  /* the WAV file could not be written */
+
+typedef struct ltc_encoder ltc_encoder;
+
+/* Create an encoder.  Free it with ltc_encoder_free(). */
+ltc_encoder *ltc_encoder_new(void);
+void ltc_encoder_free(ltc_encoder *encoder);
+
+/* Take the baud rate, tones and framing from a modem preset, such as
+ * "ltc-default", "bell202" or "kcs". */
+int ltc_encoder_set_preset(ltc_encoder *encoder, const char *name);
+
+/* Data protocol version, 1 or 2 */
+int ltc_encoder_set_protocol_version(ltc_encoder *encoder, unsigned int version);
+
+/* "high", "mid", "low", or a whole-number factor such as "8" to slow the
+ * transmission by */
+int ltc_encoder_set_encoding_rate(ltc_encoder *encoder, const char *rate);
+
+/* Nonzero to generate an OS update rather than a program upload */
+int ltc_encoder_set_os_update(ltc_encoder *encoder, int os_update);
+
+int ltc_encoder_set_baud_rate(ltc_encoder *encoder, double baud_rate);
+
+/* FSK tones in Hz, lowest symbol first.  count must be 2, 4, 8... up to 128 */
+int ltc_encoder_set_tones(ltc_encoder *encoder, const double *tones, size_t count);
+
+int ltc_encoder_set_repeat_count(ltc_encoder *encoder, uint32_t repeat_count);
+
+int ltc_encoder_set_sample_rate(ltc_encoder *encoder, double sample_rate);
+
+/* Modulate program_len bytes of program into mono samples in the range
+ * -1.0 .. 1.0.  On success *samples points to *sample_count samples,
+ * which must be released with ltc_pcm_free(). */
+int ltc_encode_pcm(const ltc_encoder *encoder,
+                   const uint8_t *program, size_t program_len,
+                   float **samples, size_t *sample_count);
+void ltc_pcm_free(float *samples, size_t sample_count);
+
+/* Modulate a program and write it to a 16-bit WAV file */
+int ltc_write_wav(const ltc_encoder *encoder,
+                  const uint8_t *program, size_t program_len,
+                  const char *filename);
+
+/* Roughly how long a program of program_len bytes takes to send, in
+ * seconds, or a negative number if encoder is NULL */
+double ltc_estimated_duration(const ltc_encoder *encoder, size_t program_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LTC_MODULATE_H */
@@ -0,0 +1,279 @@
+use core::fmt;
+
+use packet::MODE_HEADER_LEN;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// How each byte is wrapped when it is shifted out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    /// Bytes are sent back-to-back, LSB first, with no framing bits
+    Raw,
+
+    /// Each byte is preceded by a start bit (space) and followed by
+    /// one or more stop bits (mark), as a UART would send it
+    Async { stop_bits: u8 },
+}
+
+impl Framing {
+    /// Number of bit periods taken up by a single byte
+    pub fn bits_per_byte(self) -> u32 {
+        match self {
+            Framing::Raw => 8,
+            Framing::Async { stop_bits } => 1 + 8 + u32::from(stop_bits),
+        }
+    }
+
+    /// Wrap a byte in its framing bits, ready to be shifted out LSB first
+    pub fn frame(self, byte: u8) -> u32 {
+        match self {
+            Framing::Raw => u32::from(byte),
+            Framing::Async { stop_bits } => {
+                let stop = ((1u32 << stop_bits) - 1) << 9;
+                stop | (u32::from(byte) << 1)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Framing::Raw => write!(f, "Raw"),
+            Framing::Async { stop_bits } => write!(f, "Async 8N{}", stop_bits),
+        }
+    }
+}
+
+// One full cycle of cos(), scaled to i16, with the first entry repeated
+// at the end so interpolation never has to wrap.
+const COS_TABLE: [i16; 257] = [
+    32767, 32757, 32728, 32678, 32609, 32521, 32412, 32285,
+    32137, 31971, 31785, 31580, 31356, 31113, 30852, 30571,
+    30273, 29956, 29621, 29268, 28898, 28510, 28105, 27683,
+    27245, 26790, 26319, 25832, 25329, 24811, 24279, 23731,
+    23170, 22594, 22005, 21403, 20787, 20159, 19519, 18868,
+    18204, 17530, 16846, 16151, 15446, 14732, 14010, 13279,
+    12539, 11793, 11039, 10278, 9512, 8739, 7962, 7179,
+    6393, 5602, 4808, 4011, 3212, 2410, 1608, 804,
+    0, -804, -1608, -2410, -3212, -4011, -4808, -5602,
+    -6393, -7179, -7962, -8739, -9512, -10278, -11039, -11793,
+    -12539, -13279, -14010, -14732, -15446, -16151, -16846, -17530,
+    -18204, -18868, -19519, -20159, -20787, -21403, -22005, -22594,
+    -23170, -23731, -24279, -24811, -25329, -25832, -26319, -26790,
+    -27245, -27683, -28105, -28510, -28898, -29268, -29621, -29956,
+    -30273, -30571, -30852, -31113, -31356, -31580, -31785, -31971,
+    -32137, -32285, -32412, -32521, -32609, -32678, -32728, -32757,
+    -32767, -32757, -32728, -32678, -32609, -32521, -32412, -32285,
+    -32137, -31971, -31785, -31580, -31356, -31113, -30852, -30571,
+    -30273, -29956, -29621, -29268, -28898, -28510, -28105, -27683,
+    -27245, -26790, -26319, -25832, -25329, -24811, -24279, -23731,
+    -23170, -22594, -22005, -21403, -20787, -20159, -19519, -18868,
+    -18204, -17530, -16846, -16151, -15446, -14732, -14010, -13279,
+    -12539, -11793, -11039, -10278, -9512, -8739, -7962, -7179,
+    -6393, -5602, -4808, -4011, -3212, -2410, -1608, -804,
+    0, 804, 1608, 2410, 3212, 4011, 4808, 5602,
+    6393, 7179, 7962, 8739, 9512, 10278, 11039, 11793,
+    12539, 13279, 14010, 14732, 15446, 16151, 16846, 17530,
+    18204, 18868, 19519, 20159, 20787, 21403, 22005, 22594,
+    23170, 23731, 24279, 24811, 25329, 25832, 26319, 26790,
+    27245, 27683, 28105, 28510, 28898, 29268, 29621, 29956,
+    30273, 30571, 30852, 31113, 31356, 31580, 31785, 31971,
+    32137, 32285, 32412, 32521, 32609, 32678, 32728, 32757,
+    32767,
+];
+
+// Fixed-point scale of a phase or baud accumulator: 2^32 is one full turn
+const ACCUMULATOR_ONE: f64 = 4_294_967_296.0;
+
+/// Phase accumulator increment for a tone of `frequency` Hz
+pub fn phase_increment(frequency: f64, sample_rate: f64) -> u32 {
+    let incr = frequency / sample_rate * ACCUMULATOR_ONE;
+    let whole = incr as u64;
+    if incr - whole as f64 >= 0.5 {
+        (whole + 1) as u32
+    } else {
+        whole as u32
+    }
+}
+
+/// Baud accumulator increment for `baud_rate` bits per second.  This is
+/// rounded up, so a bit boundary that falls exactly on a sample is always
+/// taken on that sample.
+pub fn baud_increment(baud_rate: f64, sample_rate: f64) -> u32 {
+    let incr = baud_rate / sample_rate * ACCUMULATOR_ONE;
+    let whole = incr as u64;
+    if (whole as f64) < incr {
+        (whole + 1) as u32
+    } else {
+        whole as u32
+    }
+}
+
+/// M-FSK tone generator that uses no floating point.  The tones come from
+/// 32-bit phase accumulators and a 256-entry cosine table with linear
+/// interpolation, producing i16 samples directly.
+///
+/// `phase_incrs` holds one increment per tone, from `phase_increment()`,
+/// and can be an array or anything else that derefs to a slice.  There
+/// must be a power-of-two number of them.
+pub struct FskGenerator<T> {
+    phase: u32,
+    phase_incrs: T,
+    baud_frac: u32,
+    baud_incr: u32,
+    bits_per_symbol: u32,
+    framing: Framing,
+
+    current_symbol: usize,
+    current_word: u32,
+    bit_pos: u32,
+    data_pos: usize,
+}
+
+impl<T: AsRef<[u32]>> FskGenerator<T> {
+    pub fn new(phase_incrs: T, baud_incr: u32, framing: Framing) -> FskGenerator<T> {
+        let tones = phase_incrs.as_ref().len();
+        assert!(
+            tones >= 2 && tones.is_power_of_two(),
+            "FSK needs a power-of-two number of tones"
+        );
+        FskGenerator {
+            phase: 0,
+            phase_incrs,
+            baud_frac: 0,
+            baud_incr,
+            bits_per_symbol: tones.trailing_zeros(),
+            framing,
+
+            current_symbol: 0,
+            current_word: 0,
+            bit_pos: 0,
+            data_pos: 0,
+        }
+    }
+
+    /// Number of bits carried by each symbol
+    pub fn bits_per_symbol(&self) -> u32 {
+        self.bits_per_symbol
+    }
+
+    /// Fill `output` with the samples for `input`, carrying on from where
+    /// the previous call stopped, and return how many were written.  Pass
+    /// the same `input` until fewer than `output.len()` samples come back,
+    /// which means the burst is complete.  The call after that starts on
+    /// a new burst.
+    pub fn generate(&mut self, input: &[u8], output: &mut [i16]) -> usize {
+        let mut written = 0;
+        while written < output.len() {
+            let (baud_frac, new_symbol) = self.baud_frac.overflowing_add(self.baud_incr);
+            self.baud_frac = baud_frac;
+            if new_symbol {
+                let in_header = self.in_mode_header();
+                let mut symbol = match self.next_bit(input) {
+                    Some(bit) => bit,
+                    None => {
+                        self.data_pos = 0;
+                        return written;
+                    }
+                };
+                // The header is binary FSK on the first two tones, just
+                // as a two-tone encoder sends it.  After that, if the data
+                // runs out partway through a symbol, pad it out with mark
+                // bits as an idle line would.
+                if !in_header {
+                    for i in 1..self.bits_per_symbol {
+                        symbol |= self.next_bit(input).unwrap_or(1) << i;
+                    }
+                }
+                self.current_symbol = symbol as usize;
+            }
+            output[written] = cos(self.phase);
+            self.phase = self
+                .phase
+                .wrapping_add(self.phase_incrs.as_ref()[self.current_symbol]);
+            written += 1;
+        }
+        written
+    }
+
+    /// Fill `output` with a steady tone, phase-continuous with the bursts
+    /// either side, to pad around a burst.  Before a burst (`lead_in`) this
+    /// is the lowest tone, which the preamble starts on, and after one it
+    /// holds the last symbol's tone.  The bit timing is left alone.
+    pub fn carrier(&mut self, lead_in: bool, output: &mut [i16]) {
+        let phase_incr = self.phase_incrs.as_ref()[if lead_in { 0 } else { self.current_symbol }];
+        for sample in output.iter_mut() {
+            *sample = cos(self.phase);
+            self.phase = self.phase.wrapping_add(phase_incr);
+        }
+    }
+
+    /// Generate a whole burst at once
+    #[cfg(feature = "alloc")]
+    pub fn generate_vec(&mut self, input: &[u8]) -> Vec<i16> {
+        const CHUNK: usize = 4096;
+        let mut output: Vec<i16> = Vec::new();
+        loop {
+            let start = output.len();
+            output.resize(start + CHUNK, 0);
+            let written = self.generate(input, &mut output[start..]);
+            output.truncate(start + written);
+            if written < CHUNK {
+                return output;
+            }
+        }
+    }
+
+    // Whether the next bit belongs to the part of the burst that is sent
+    // one bit per symbol
+    fn in_mode_header(&self) -> bool {
+        let byte = if self.bit_pos > 0 { self.data_pos - 1 } else { self.data_pos };
+        byte < MODE_HEADER_LEN
+    }
+
+    // Pull the next framed bit out of the input, LSB first
+    fn next_bit(&mut self, input: &[u8]) -> Option<u32> {
+        if self.bit_pos == 0 {
+            if self.data_pos < input.len() {
+                self.current_word = self.framing.frame(input[self.data_pos]);
+                self.data_pos += 1;
+                self.bit_pos = self.framing.bits_per_byte();
+            } else {
+                return None;
+            }
+        }
+        let bit = self.current_word & 1;
+        self.current_word >>= 1;
+        self.bit_pos -= 1;
+        Some(bit)
+    }
+}
+
+// Look up cos() of a 32-bit phase, interpolating between table entries
+fn cos(phase: u32) -> i16 {
+    let index = (phase >> 24) as usize;
+    let frac = ((phase >> 8) & 0xffff) as i32;
+    let a = i32::from(COS_TABLE[index]);
+    let b = i32::from(COS_TABLE[index + 1]);
+    (a + (((b - a) * frac) >> 16)) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn async_framing() {
+        // Start bit first, then the byte LSB first, then the stop bits
+        assert_eq!(Framing::Raw.frame(0xa5), 0xa5);
+        assert_eq!(Framing::Async { stop_bits: 1 }.frame(0xa5), 0x34a);
+        assert_eq!(Framing::Async { stop_bits: 2 }.frame(0x00), 0x600);
+        assert_eq!(Framing::Async { stop_bits: 2 }.frame(0xff), 0x7fe);
+
+        assert_eq!(Framing::Raw.bits_per_byte(), 8);
+        assert_eq!(Framing::Async { stop_bits: 1 }.bits_per_byte(), 10);
+        assert_eq!(Framing::Async { stop_bits: 2 }.bits_per_byte(), 11);
+    }
+}
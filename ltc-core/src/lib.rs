@@ -0,0 +1,16 @@
+//! The parts of ltc-modulate that a microcontroller needs: building the
+//! packets that carry a program, and turning them into FSK samples.
+//!
+//! Nothing here needs `std` or an allocator.  Everything writes into
+//! buffers supplied by the caller, so the same framing code can run in
+//! bootloader test firmware or an uploader dongle.  Enable the `alloc`
+//! feature for versions that return a `Vec` instead.
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod fsk;
+pub mod md5;
+pub mod murmur3;
+pub mod packet;
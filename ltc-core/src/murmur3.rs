@@ -0,0 +1,79 @@
+/// 32-bit MurmurHash3 (the x86 variant), as used for the packet footers
+/// and the program hash in the control packet
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut hash = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k = u32::from(chunk[0])
+            | (u32::from(chunk[1]) << 8)
+            | (u32::from(chunk[2]) << 16)
+            | (u32::from(chunk[3]) << 24);
+        hash ^= mix(k);
+        hash = hash.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, byte| (k << 8) | u32::from(*byte));
+        hash ^= mix(k);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"", 1), 0x514e_28b7);
+        assert_eq!(murmur3_32(b"", 0xffff_ffff), 0x81f1_6f39);
+    }
+
+    // Whole blocks, then every tail length, with and without a seed
+    #[test]
+    fn tails() {
+        assert_eq!(murmur3_32(&[0x21, 0x43, 0x65, 0x87], 0), 0xf55b_516b);
+        assert_eq!(murmur3_32(&[0x21, 0x43, 0x65, 0x87], 0x5082_edee), 0x2362_f9de);
+        assert_eq!(murmur3_32(&[0x21, 0x43, 0x65], 0), 0x7e4a_8634);
+        assert_eq!(murmur3_32(&[0x21, 0x43], 0), 0xa0f7_b07a);
+        assert_eq!(murmur3_32(&[0x21], 0), 0x7266_1cf4);
+        assert_eq!(murmur3_32(&[0xff, 0xff, 0xff, 0xff], 0), 0x7629_3b50);
+        assert_eq!(murmur3_32(&[0, 0, 0, 0], 0), 0x2362_f9de);
+        assert_eq!(murmur3_32(&[0, 0, 0], 0), 0x85f0_b427);
+        assert_eq!(murmur3_32(&[0, 0], 0), 0x30f4_c306);
+        assert_eq!(murmur3_32(&[0], 0), 0x514e_28b7);
+
+        assert_eq!(murmur3_32(b"abcd", 0x9747_b28c), 0xf047_8627);
+        assert_eq!(murmur3_32(b"abc", 0x9747_b28c), 0xc84a_62dd);
+        assert_eq!(murmur3_32(b"ab", 0x9747_b28c), 0x7487_5592);
+        assert_eq!(murmur3_32(b"a", 0x9747_b28c), 0x7fa0_9ea6);
+        assert_eq!(murmur3_32(b"aaaa", 0x9747_b28c), 0x5a97_808a);
+        assert_eq!(murmur3_32(b"aaa", 0x9747_b28c), 0x283e_0130);
+        assert_eq!(murmur3_32(b"aa", 0x9747_b28c), 0x5d21_1726);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(murmur3_32(b"Hello, world!", 0x9747_b28c), 0x2488_4cba);
+        assert_eq!(
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0x9747_b28c),
+            0x2fa8_26cd
+        );
+    }
+}
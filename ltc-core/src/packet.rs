@@ -0,0 +1,173 @@
+use md5;
+use murmur3;
+
+/// Which version of the data strip pattern is used
+#[derive(Clone, Copy, Debug)]
+pub enum ProtocolVersion {
+    /// Original v1 (0xaa, 0x55)
+    V1,
+
+    /// Improved v2 (0x35, 0xac, 0x95)
+    V2,
+}
+
+impl ProtocolVersion {
+    pub fn as_num(self) -> u8 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+}
+
+/// Preamble sent before every audio packet
+pub const PREAMBLE: [u8; 7] = [0x00, 0x00, 0x00, 0x00, 0xaa, 0x55, 0x42];
+
+/// Stop bits, sent to pad the end of transmission
+pub const STOP_BYTES: [u8; 1] = [0xff];
+
+/// Number of program bytes carried by each data packet
+pub const DATA_PAYLOAD_LEN: usize = 256;
+
+// Preamble, version, packet type and two bytes of block number
+const HEADER_LEN: usize = PREAMBLE.len() + 4;
+
+/// Bytes at the start of every burst, up to and including the version
+/// byte, that are sent one bit per symbol whatever the modulation mode.
+/// A receiver can read the mode from the version byte before the number
+/// of bits per symbol changes.
+pub const MODE_HEADER_LEN: usize = PREAMBLE.len() + 1;
+
+// Murmur3 hash of everything after the preamble
+const FOOTER_LEN: usize = 4;
+
+/// Size of a complete control packet
+pub const CONTROL_PACKET_LEN: usize = HEADER_LEN + 4 + 4 + 16 + FOOTER_LEN + STOP_BYTES.len();
+
+/// Size of a complete data packet
+pub const DATA_PACKET_LEN: usize = HEADER_LEN + DATA_PAYLOAD_LEN + FOOTER_LEN + STOP_BYTES.len();
+
+// Packet types
+const CONTROL_PACKET: u8 = 0x01;
+const DATA_PACKET: u8 = 0x02;
+const CONTROL_OS_PACKET: u8 = 0x03;
+const DATA_OS_PACKET: u8 = 0x04;
+
+// Position of the modulation mode within the version byte
+const MODE_SHIFT: u8 = 4;
+
+/// Builds the control and data packets that carry a program
+pub struct PacketBuilder {
+    protocol_version: ProtocolVersion,
+    mode: u8,
+    os_update: bool,
+}
+
+impl PacketBuilder {
+    /// `mode` is the four-bit modulation code from the modulator.  The low
+    /// nibble of the version byte is the protocol version, and the high
+    /// nibble is the mode, so a receiver can tell which scheme a packet
+    /// uses.  It is always zero for binary FSK, which keeps those packets
+    /// unchanged.
+    pub fn new(protocol_version: ProtocolVersion, mode: u8, os_update: bool) -> PacketBuilder {
+        PacketBuilder {
+            protocol_version,
+            mode,
+            os_update,
+        }
+    }
+
+    fn version_byte(&self) -> u8 {
+        debug_assert!(self.mode <= 0xf, "mode {:#x} doesn't fit in a nibble", self.mode);
+        self.protocol_version.as_num() | (self.mode << MODE_SHIFT)
+    }
+
+    fn write_header(&self, packet: &mut [u8], packet_type: u8, block_number: u16) {
+        packet[..PREAMBLE.len()].copy_from_slice(&PREAMBLE);
+        packet[PREAMBLE.len()] = self.version_byte();
+        packet[PREAMBLE.len() + 1] = packet_type;
+        packet[PREAMBLE.len() + 2] = (block_number & 0xff) as u8;
+        packet[PREAMBLE.len() + 3] = ((block_number >> 8) & 0xff) as u8;
+    }
+
+    /// Build the control packet describing `program`: its length, a hash
+    /// of its contents and an MD5 GUID
+    pub fn control_packet(&self, program: &[u8], packet: &mut [u8; CONTROL_PACKET_LEN]) {
+        let packet_type = if self.os_update {
+            CONTROL_OS_PACKET
+        } else {
+            CONTROL_PACKET
+        };
+        self.write_header(packet, packet_type, 0);
+
+        let mut pos = HEADER_LEN;
+        packet[pos..pos + 4].copy_from_slice(&(program.len() as u32).to_le_bytes());
+        pos += 4;
+        packet[pos..pos + 4].copy_from_slice(&murmur3::murmur3_32(program, 0x32d0_babe).to_le_bytes());
+        pos += 4;
+        packet[pos..pos + 16].copy_from_slice(&md5::digest(program));
+        pos += 16;
+
+        write_footer(packet, pos);
+    }
+
+    /// Build data packet `block_number`.  The payload is padded out to
+    /// `DATA_PAYLOAD_LEN` bytes with 0xff, and anything past that is ignored.
+    pub fn data_packet(&self, payload: &[u8], block_number: u16, packet: &mut [u8; DATA_PACKET_LEN]) {
+        let packet_type = if self.os_update {
+            DATA_OS_PACKET
+        } else {
+            DATA_PACKET
+        };
+        self.write_header(packet, packet_type, block_number);
+
+        let data = &mut packet[HEADER_LEN..HEADER_LEN + DATA_PAYLOAD_LEN];
+        let used = payload.len().min(DATA_PAYLOAD_LEN);
+        data[..used].copy_from_slice(&payload[..used]);
+        for byte in data[used..].iter_mut() {
+            *byte = 0xff;
+        }
+
+        write_footer(packet, HEADER_LEN + DATA_PAYLOAD_LEN);
+
+        // After the hash has been computed, stripe the data portion
+        // with a pattern of 0x55 and 0xaa.  This provides some level
+        // of DC balance, even at the end where we have lots of 0xff.
+        match self.protocol_version {
+            ProtocolVersion::V1 => {
+                for i in 0..DATA_PAYLOAD_LEN {
+                    if (i % 16) == 3 {
+                        packet[i + HEADER_LEN] ^= 0x55;
+                    } else if (i % 16) == 11 {
+                        packet[i + HEADER_LEN] ^= 0xaa;
+                    }
+                }
+            }
+
+            ProtocolVersion::V2 => {
+                // modulate the packet # and payload
+                // so skip preamble + version + type (7 bytes preamble + 1 byte version + 1 byte type = 9)
+                // and then "add 2" in the modular math loop because on the demod side we are 2-offset
+                // also skip capping hash and stop bytes
+                let mod_range = (PREAMBLE.len() + 2)..(DATA_PACKET_LEN - (FOOTER_LEN + STOP_BYTES.len()));
+                for i in mod_range {
+                    if ((i - 9 + 2) % 3) == 0 {
+                        packet[i] ^= 0x35;
+                    } else if ((i - 9 + 2) % 3) == 1 {
+                        packet[i] ^= 0xac;
+                    } else if ((i - 9 + 2) % 3) == 2 {
+                        packet[i] ^= 0x95;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Hash everything between the preamble and `end`, and follow it with the
+// hash and the stop bytes
+fn write_footer(packet: &mut [u8], end: usize) {
+    let hash = murmur3::murmur3_32(&packet[PREAMBLE.len()..end], 0xdead_beef);
+    packet[end..end + FOOTER_LEN].copy_from_slice(&hash.to_le_bytes());
+    packet[end + FOOTER_LEN..end + FOOTER_LEN + STOP_BYTES.len()].copy_from_slice(&STOP_BYTES);
+}
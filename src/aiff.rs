@@ -0,0 +1,242 @@
+extern crate byteorder;
+use std;
+use std::io::prelude::*;
+use std::fs::File;
+use self::byteorder::{BigEndian, WriteBytesExt};
+
+use wav::{Cue, SampleFormat, WavOptions};
+
+// AIFF-C version chunk timestamp, the only version there is
+const AIFC_VERSION_1: u32 = 0xa280_5140;
+
+const FLOAT_COMPRESSION_NAME: &str = "32-bit floating point";
+
+/// Write an AIFF file using the same options as `wav::write_wav`.  Float
+/// samples are written as AIFF-C, and `U8` becomes signed 8-bit.  Cue
+/// points become markers, at the start of each region.
+pub fn write_aiff(rate: u32, samples: &[f64], options: &WavOptions, filename: &str) -> std::io::Result<()> {
+    let mut file = File::create(filename)?;
+    write_aiff_to(rate, samples, options, &mut file)
+}
+
+/// Write an AIFF file to anything that implements `Write`.  Samples are in
+/// the range -1.0 .. 1.0.
+pub fn write_aiff_to<W: Write>(rate: u32, samples: &[f64], options: &WavOptions, file: &mut W) -> std::io::Result<()> {
+    let format = options.format;
+    let layout = options.layout;
+    let num_channels = layout.channels();
+    let aifc = format == SampleFormat::F32;
+
+    let mut data = Vec::with_capacity(samples.len() * usize::from(num_channels) * format.bytes_per_sample() as usize);
+    for sample in samples {
+        for channel in 0..usize::from(num_channels) {
+            format.encode_be(layout.channel_value(*sample, channel), &mut data);
+        }
+    }
+
+    let mut comm = vec![];
+    /* numChannels */   comm.write_u16::<BigEndian>(num_channels)?;
+    /* numFrames */     comm.write_u32::<BigEndian>(samples.len() as u32)?;
+    /* sampleSize */    comm.write_u16::<BigEndian>(format.bits_per_sample())?;
+    /* sampleRate */    comm.write_all(&extended(rate))?;
+    if aifc {
+        /* compression */ comm.write_all(b"fl32")?;
+        write_pstring(&mut comm, FLOAT_COMPRESSION_NAME)?;
+    }
+
+    let mut chunks = vec![];
+    if aifc {
+        write_chunk(&mut chunks, b"FVER", &AIFC_VERSION_1.to_be_bytes())?;
+    }
+    write_chunk(&mut chunks, b"COMM", &comm)?;
+    for (id, text) in &options.info {
+        let chunk_id = if id == b"INAM" { b"NAME" } else { b"ANNO" };
+        write_chunk(&mut chunks, chunk_id, text.as_bytes())?;
+    }
+    if !options.cues.is_empty() {
+        write_chunk(&mut chunks, b"MARK", &make_markers(&options.cues)?)?;
+    }
+
+    let mut ssnd = Vec::with_capacity(8 + data.len());
+    /* offset */        ssnd.write_u32::<BigEndian>(0)?;
+    /* blockSize */     ssnd.write_u32::<BigEndian>(0)?;
+    ssnd.extend(data);
+    write_chunk(&mut chunks, b"SSND", &ssnd)?;
+
+    let form_len = 4 + chunks.len() as u64;
+    if form_len > u64::from(u32::MAX) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "AIFF file would be larger than 4 GiB",
+        ));
+    }
+    /* chunkId */       file.write_all(b"FORM")?;
+    /* chunkSize */     file.write_u32::<BigEndian>(form_len as u32)?;
+    /* formType */      file.write_all(if aifc { b"AIFC" } else { b"AIFF" })?;
+    file.write_all(&chunks)?;
+
+    Ok(())
+}
+
+// Append a chunk, padded to an even length
+fn write_chunk(output: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    output.write_all(id)?;
+    output.write_u32::<BigEndian>(data.len() as u32)?;
+    output.write_all(data)?;
+    if data.len() & 1 != 0 {
+        output.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+// A length byte followed by up to 255 bytes of text, padded so that the
+// whole string has an even length
+fn write_pstring(output: &mut Vec<u8>, text: &str) -> std::io::Result<()> {
+    let text = &text.as_bytes()[..text.len().min(255)];
+    output.write_u8(text.len() as u8)?;
+    output.write_all(text)?;
+    if text.len() & 1 == 0 {
+        output.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+// List every cue point in a MARK chunk
+fn make_markers(cues: &[Cue]) -> std::io::Result<Vec<u8>> {
+    let mut mark = vec![];
+    mark.write_u16::<BigEndian>(cues.len().min(usize::from(u16::MAX)) as u16)?;
+    for (id, point) in (1..=u16::MAX).zip(cues) {
+        /* id */        mark.write_u16::<BigEndian>(id)?;
+        /* position */  mark.write_u32::<BigEndian>(point.position)?;
+        write_pstring(&mut mark, &point.label)?;
+    }
+    Ok(mark)
+}
+
+// The sample rate as an 80-bit IEEE 754 extended float: a 15-bit
+// exponent, then a 64-bit mantissa with an explicit leading one
+fn extended(rate: u32) -> [u8; 10] {
+    let mut bytes = [0; 10];
+    if rate != 0 {
+        let rate = u64::from(rate);
+        let shift = rate.leading_zeros();
+        let exponent = 16383 + 63 - shift as u16;
+        bytes[..2].copy_from_slice(&exponent.to_be_bytes());
+        bytes[2..].copy_from_slice(&(rate << shift).to_be_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be16(bytes: &[u8]) -> u16 {
+        u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+    }
+
+    fn be32(bytes: &[u8]) -> u32 {
+        u32::from(be16(bytes)) << 16 | u32::from(be16(&bytes[2..]))
+    }
+
+    // Check the FORM header and split the rest into chunk ids and contents
+    fn chunks<'a>(file: &'a [u8], form_type: &[u8]) -> Vec<([u8; 4], &'a [u8])> {
+        assert_eq!(&file[..4], b"FORM");
+        assert_eq!(be32(&file[4..]) as usize, file.len() - 8);
+        assert_eq!(&file[8..12], form_type);
+        let mut bytes = &file[12..];
+        let mut chunks = vec![];
+        while !bytes.is_empty() {
+            let mut id = [0; 4];
+            id.copy_from_slice(&bytes[..4]);
+            let len = be32(&bytes[4..]) as usize;
+            chunks.push((id, &bytes[8..8 + len]));
+            bytes = &bytes[8 + len + (len & 1)..];
+        }
+        chunks
+    }
+
+    fn write(samples: &[f64], options: &WavOptions) -> Vec<u8> {
+        let mut file = vec![];
+        write_aiff_to(44100, samples, options, &mut file).unwrap();
+        file
+    }
+
+    #[test]
+    fn sample_rates() {
+        assert_eq!(extended(44100), [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extended(48000), [0x40, 0x0e, 0xbb, 0x80, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extended(8000), [0x40, 0x0b, 0xfa, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extended(1), [0x3f, 0xff, 0x80, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extended(0), [0; 10]);
+    }
+
+    #[test]
+    fn pcm() {
+        let options = WavOptions {
+            layout: ::wav::ChannelLayout::Both,
+            info: vec![(*b"INAM", "odd".to_owned()), (*b"ICMT", "note".to_owned())],
+            ..WavOptions::default()
+        };
+        let file = write(&[0.5, -0.5, 0.0], &options);
+        let chunks = chunks(&file, b"AIFF");
+        let ids: Vec<&[u8]> = chunks.iter().map(|c| &c.0[..]).collect();
+        assert_eq!(ids, [&b"COMM"[..], b"NAME", b"ANNO", b"SSND"]);
+
+        let comm = chunks[0].1;
+        assert_eq!(comm.len(), 18);
+        assert_eq!(be16(comm), 2);
+        assert_eq!(be32(&comm[2..]), 3);
+        assert_eq!(be16(&comm[6..]), 16);
+        assert_eq!(comm[8..], extended(44100));
+
+        assert_eq!(chunks[1].1, b"odd");
+        assert_eq!(chunks[2].1, b"note");
+
+        let ssnd = chunks[3].1;
+        assert_eq!(be32(ssnd), 0);
+        assert_eq!(be32(&ssnd[4..]), 0);
+        assert_eq!(&ssnd[8..], [0x40, 0x00, 0x40, 0x00, 0xc0, 0x00, 0xc0, 0x00, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn float_is_aifc() {
+        let options = WavOptions {
+            format: SampleFormat::F32,
+            ..WavOptions::default()
+        };
+        let file = write(&[0.25, -1.0], &options);
+        let chunks = chunks(&file, b"AIFC");
+        assert_eq!(chunks[0].0, *b"FVER");
+        assert_eq!(be32(chunks[0].1), AIFC_VERSION_1);
+
+        let comm = chunks[1].1;
+        assert_eq!(be16(&comm[6..]), 32);
+        assert_eq!(&comm[18..22], b"fl32");
+        assert_eq!(usize::from(comm[22]), FLOAT_COMPRESSION_NAME.len());
+        assert_eq!(&comm[23..23 + FLOAT_COMPRESSION_NAME.len()], FLOAT_COMPRESSION_NAME.as_bytes());
+        assert_eq!(comm.len() % 2, 0);
+
+        let ssnd = chunks[2].1;
+        assert_eq!(&ssnd[8..12], 0.25f32.to_be_bytes());
+        assert_eq!(&ssnd[12..], (-1.0f32).to_be_bytes());
+    }
+
+    #[test]
+    fn markers() {
+        let options = WavOptions {
+            cues: vec![
+                Cue { position: 0, length: 5, label: "Start".to_owned() },
+                Cue { position: 70000, length: 0, label: "Block 0".to_owned() },
+            ],
+            ..WavOptions::default()
+        };
+        let file = write(&[0.0; 4], &options);
+        let chunks = chunks(&file, b"AIFF");
+        assert_eq!(chunks[1].0, *b"MARK");
+        assert_eq!(
+            chunks[1].1,
+            &b"\x00\x02\x00\x01\x00\x00\x00\x00\x05Start\x00\x02\x00\x01\x11\x70\x07Block 0"[..]
+        );
+    }
+}
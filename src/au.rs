@@ -0,0 +1,172 @@
+extern crate byteorder;
+use std;
+use std::io::prelude::*;
+use std::fs::File;
+use self::byteorder::{BigEndian, WriteBytesExt};
+
+use wav::{SampleFormat, WavOptions};
+
+// Data size meaning "read until the end of the file"
+const UNKNOWN_SIZE: u32 = 0xffff_ffff;
+
+// Fixed part of the header, before the annotation
+const HEADER_LEN: usize = 24;
+
+// Longest annotation text.  Some readers refuse headers much over 100
+// bytes, so only a short title is kept.
+const MAX_ANNOTATION_LEN: usize = 63;
+
+/// Write a Sun/NeXT .au file using the same options as `wav::write_wav`.
+/// `U8` becomes signed 8-bit, the `INAM` title goes in the annotation, and
+/// cue points are not written.
+pub fn write_au(rate: u32, samples: &[f64], options: &WavOptions, filename: &str) -> std::io::Result<()> {
+    let mut file = File::create(filename)?;
+    write_au_to(rate, samples, options, &mut file)
+}
+
+/// Write a .au file to anything that implements `Write`.  Samples are in
+/// the range -1.0 .. 1.0.
+pub fn write_au_to<W: Write>(rate: u32, samples: &[f64], options: &WavOptions, file: &mut W) -> std::io::Result<()> {
+    let format = options.format;
+    let layout = options.layout;
+    let num_channels = layout.channels();
+    let encoding = match format {
+        SampleFormat::U8 => 2,
+        SampleFormat::S16 => 3,
+        SampleFormat::S24 => 4,
+        SampleFormat::S32 => 5,
+        SampleFormat::F32 => 6,
+    };
+
+    let mut data = Vec::with_capacity(samples.len() * usize::from(num_channels) * format.bytes_per_sample() as usize);
+    for sample in samples {
+        for channel in 0..usize::from(num_channels) {
+            format.encode_be(layout.channel_value(*sample, channel), &mut data);
+        }
+    }
+
+    // A NUL-terminated note, padded so the samples start on an 8-byte
+    // boundary
+    let mut annotation: Vec<u8> = options
+        .info
+        .iter()
+        .find(|(id, _)| id == b"INAM")
+        .map(|(_, text)| text.bytes().take(MAX_ANNOTATION_LEN).collect())
+        .unwrap_or_default();
+    annotation.push(0);
+    while (HEADER_LEN + annotation.len()) % 8 != 0 {
+        annotation.push(0);
+    }
+
+    // Files past 4 GiB are still readable, with the size left unknown
+    let data_len = if data.len() as u64 > u64::from(UNKNOWN_SIZE) {
+        UNKNOWN_SIZE
+    } else {
+        data.len() as u32
+    };
+
+    /* magic */         file.write_all(b".snd")?;
+    /* dataOffset */    file.write_u32::<BigEndian>((HEADER_LEN + annotation.len()) as u32)?;
+    /* dataSize */      file.write_u32::<BigEndian>(data_len)?;
+    /* encoding */      file.write_u32::<BigEndian>(encoding)?;
+    /* sampleRate */    file.write_u32::<BigEndian>(rate)?;
+    /* channels */      file.write_u32::<BigEndian>(u32::from(num_channels))?;
+    file.write_all(&annotation)?;
+    file.write_all(&data)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(bytes: &[u8]) -> u32 {
+        u32::from(bytes[0]) << 24 | u32::from(bytes[1]) << 16 | u32::from(bytes[2]) << 8 | u32::from(bytes[3])
+    }
+
+    fn write(samples: &[f64], options: &WavOptions) -> Vec<u8> {
+        let mut file = vec![];
+        write_au_to(8000, samples, options, &mut file).unwrap();
+        file
+    }
+
+    #[test]
+    fn header() {
+        let options = WavOptions {
+            info: vec![(*b"INAM", "Block".to_owned())],
+            ..WavOptions::default()
+        };
+        let file = write(&[0.0; 3], &options);
+        assert_eq!(&file[..4], b".snd");
+        assert_eq!(be32(&file[4..]), 32);
+        assert_eq!(be32(&file[8..]), 6);
+        assert_eq!(be32(&file[12..]), 3);
+        assert_eq!(be32(&file[16..]), 8000);
+        assert_eq!(be32(&file[20..]), 1);
+        assert_eq!(&file[24..32], b"Block\0\0\0");
+        assert_eq!(file.len(), 32 + 6);
+    }
+
+    #[test]
+    fn annotation_alignment() {
+        for len in 0..20 {
+            let options = WavOptions {
+                info: vec![(*b"INAM", "x".repeat(len))],
+                ..WavOptions::default()
+            };
+            let file = write(&[], &options);
+            let offset = be32(&file[4..]) as usize;
+            assert_eq!(offset % 8, 0);
+            assert_eq!(offset, file.len());
+            assert_eq!(file[24 + len], 0);
+        }
+        let long = WavOptions {
+            info: vec![(*b"INAM", "x".repeat(200))],
+            ..WavOptions::default()
+        };
+        assert_eq!(be32(&write(&[], &long)[4..]), 88);
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples = [0.0, 0.5, -0.5, 1.0, -1.0];
+        for &(format, encoding) in &[
+            (SampleFormat::U8, 2),
+            (SampleFormat::S16, 3),
+            (SampleFormat::S24, 4),
+            (SampleFormat::S32, 5),
+            (SampleFormat::F32, 6),
+        ] {
+            let options = WavOptions {
+                format,
+                layout: ::wav::ChannelLayout::Both,
+                ..WavOptions::default()
+            };
+            let file = write(&samples, &options);
+            assert_eq!(be32(&file[12..]), encoding);
+            assert_eq!(be32(&file[20..]), 2);
+
+            let width = format.bytes_per_sample() as usize;
+            let data = &file[be32(&file[4..]) as usize..];
+            assert_eq!(data.len(), be32(&file[8..]) as usize);
+            assert_eq!(data.len(), samples.len() * 2 * width);
+            for (frame, sample) in data.chunks(2 * width).zip(&samples) {
+                let (left, right) = frame.split_at(width);
+                assert_eq!(left, right);
+                let mut value = 0u32;
+                for byte in left {
+                    value = value << 8 | u32::from(*byte);
+                }
+                let decoded = if format == SampleFormat::F32 {
+                    f64::from(f32::from_bits(value))
+                } else {
+                    let bits = 8 * width as u32;
+                    let signed = (value << (32 - bits)) as i32 >> (32 - bits);
+                    f64::from(signed) / f64::from(1u32 << (bits - 1))
+                };
+                assert!((decoded - sample).abs() <= 1.0 / f64::from(1u32 << (8 * width as u32 - 1).min(23)));
+            }
+        }
+    }
+}
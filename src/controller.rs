@@ -1,222 +1,122 @@
 use modulator;
-extern crate byteorder;
-extern crate crypto;
-extern crate murmur3;
 
-use self::crypto::digest::Digest;
-use self::crypto::md5::Md5;
+use ltc_core::packet::{self, PacketBuilder};
+pub use ltc_core::packet::ProtocolVersion;
 
 use ::EncodingRate;
 
-use self::byteorder::{LittleEndian, WriteBytesExt};
-use std::io::Cursor;
-
-/// Which version of the data strip pattern is used
+/// Shape applied to every modulated burst, to avoid clicks as the
+/// audio starts and stops.  The fades are on carrier added either side
+/// of the burst, so they lengthen it rather than eating into it.
 #[derive(Clone, Copy, Debug)]
-pub enum ProtocolVersion {
-    /// Original v1 (0xaa, 0x55)
-    V1,
+pub struct Envelope {
+    /// Length of the raised-cosine fade-in, in milliseconds
+    pub fade_in_ms: f64,
+
+    /// Length of the raised-cosine fade-out, in milliseconds
+    pub fade_out_ms: f64,
+
+    /// Overall output level, where 1.0 is full scale
+    pub gain: f64,
+}
+
+impl Envelope {
+    /// No fades, at full level, so each burst is exactly what the
+    /// modulator produced
+    pub fn none() -> Envelope {
+        Envelope {
+            fade_in_ms: 0.0,
+            fade_out_ms: 0.0,
+            gain: 1.0,
+        }
+    }
+}
+
+/// What a stretch of the output holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Silence,
+    ControlPacket,
+    DataPacket(u16),
+    Pilot,
 
-    /// Improved v2 (0x35, 0xac, 0x95)
-    V2,
+    /// One complete pass through the program, counting from 1
+    Repeat(u32),
 }
 
-impl ProtocolVersion {
-    pub fn as_num(self) -> u8 {
-        match self {
-            ProtocolVersion::V1 => 1,
-            ProtocolVersion::V2 => 2,
+impl std::fmt::Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Segment::Silence => write!(f, "Silence"),
+            Segment::ControlPacket => write!(f, "Control packet"),
+            Segment::DataPacket(block) => write!(f, "Block {}", block),
+            Segment::Pilot => write!(f, "Pilot"),
+            Segment::Repeat(repeat) => write!(f, "Repeat {}", repeat),
         }
     }
 }
 
+/// A stretch of the output, in samples
+#[derive(Clone, Copy, Debug)]
+pub struct Marker {
+    pub start: usize,
+    pub length: usize,
+    pub segment: Segment,
+}
+
+// Gaps around the packets of an upload, in milliseconds before stretching
+const LEAD_IN_MS: u32 = 250;
+const CONTROL_GAP_MS: u32 = 100;
+const DATA_START_MS: u32 = 500;
+const DATA_GAP_MS: u32 = 80;
+const TRAILER_MS: u32 = 500;
+
+// Length of the pilot tone sent after each slow upload, in bits
+const PILOT_BITS: u32 = 4000;
+
+/// Transmissions stretched by at least this much are slow enough to need
+/// a pilot tone after each pass
+pub const PILOT_MIN_STRETCH: u32 = 4;
+
 pub struct Controller {
     rate: f64,
     os_update: bool,
-    modulator: modulator::Modulator,
+    envelope: Envelope,
+    modulator: Box<dyn modulator::Modulate>,
     protocol_version: ProtocolVersion,
-    preamble: Vec<u8>,
-    stop_bytes: Vec<u8>,
-}
-
-// Preamble sent before every audio packet
-const PREAMBLE: [u8; 7] = [0x00, 0x00, 0x00, 0x00, 0xaa, 0x55, 0x42];
-
-// Stop bits, sent to pad the end of transmission
-const STOP_BYTES: [u8; 1] = [0xff];
+    markers: Vec<Marker>,
 
-// Packet types
-const CONTROL_PACKET: u8 = 0x01;
-const DATA_PACKET: u8 = 0x02;
-const CONTROL_OS_PACKET: u8 = 0x03;
-const DATA_OS_PACKET: u8 = 0x04;
+    // Samples produced so far in this transmission
+    position: usize,
+}
 
 impl Controller {
-    pub fn new(sample_rate: f64, os_update: bool, protocol_version: ProtocolVersion, baud_rate: f64, f_lo: f64, f_hi: f64,) -> Controller {
+    pub fn new(sample_rate: f64, os_update: bool, protocol_version: ProtocolVersion, modulator: Box<dyn modulator::Modulate>, envelope: Envelope) -> Controller {
         Controller {
             rate: sample_rate,
             os_update,
+            envelope,
             protocol_version,
-            modulator: modulator::Modulator::new(sample_rate, baud_rate, f_lo, f_hi),
-            preamble: PREAMBLE.to_vec(),
-            stop_bytes: STOP_BYTES.to_vec(),
+            modulator,
+            markers: vec![],
+            position: 0,
         }
     }
 
-    pub fn make_preamble(&self) -> Vec<u8> {
-        let mut header = vec![];
-        for byte in &self.preamble {
-            header.push(*byte);
-        }
-        header
-    }
-
-    pub fn make_control_header(&self) -> Vec<u8> {
-        let mut header = self.make_preamble();
-        header.push(self.protocol_version.as_num());
-        header.push(CONTROL_PACKET);
-        header.push(0x00);
-        header.push(0x00);
-        header
-    }
-
-    pub fn make_data_header(&self, block_number: u16) -> Vec<u8> {
-        let mut header = self.make_preamble();
-        header.push(self.protocol_version.as_num());
-        header.push(DATA_PACKET);
-        header.push((block_number & 0xff) as u8);
-        header.push(((block_number >> 8) & 0xff) as u8);
-        header
-    }
-
-    pub fn make_control_os_header(&self) -> Vec<u8> {
-        let mut header = self.make_preamble();
-        header.push(self.protocol_version.as_num());
-        header.push(CONTROL_OS_PACKET);
-        header.push(0x00);
-        header.push(0x00);
-        header
-    }
-
-    pub fn make_data_os_header(&self, block_number: u16) -> Vec<u8> {
-        let mut header = self.make_preamble();
-        header.push(self.protocol_version.as_num());
-        header.push(DATA_OS_PACKET);
-        header.push((block_number & 0xff) as u8);
-        header.push(((block_number >> 8) & 0xff) as u8);
-        header
-    }
-
-    pub fn append_data(&self, buffer: &mut Vec<u8>, data: &[u8]) {
-        for byte in data.iter() {
-            buffer.push(*byte);
-        }
-    }
-
-    pub fn make_footer(&self, data: &[u8]) -> Vec<u8> {
-        let hash = 0xdead_beefu32;
-        let mut data_cursor = Cursor::new(data);
-        data_cursor.set_position(7); // seek past the data header
-        let data_hash_32 = murmur3::murmur3_32(&mut data_cursor, hash);
-        let mut data_hash = vec![];
-        data_hash.write_u32::<LittleEndian>(data_hash_32).unwrap();
-        data_hash
+    fn packet_builder(&self) -> PacketBuilder {
+        PacketBuilder::new(self.protocol_version, self.modulator.mode(), self.os_update)
     }
 
     pub fn make_control_packet(&mut self, data: &[u8]) -> Vec<u8> {
-        let mut packet = vec![];
-
-        let control_header = if self.os_update {
-            self.make_control_os_header()
-        } else {
-            self.make_control_header()
-        };
-        self.append_data(&mut packet, &control_header);
-
-        let mut program_length = vec![];
-        program_length
-            .write_u32::<LittleEndian>(data.len() as u32)
-            .unwrap();
-        self.append_data(&mut packet, &program_length);
-
-        let program_hash_32 = murmur3::murmur3_32(&mut Cursor::new(&data), 0x32d0_babe);
-        let mut program_hash = vec![];
-        program_hash
-            .write_u32::<LittleEndian>(program_hash_32)
-            .unwrap();
-        self.append_data(&mut packet, &program_hash);
-
-        let mut program_guid_hasher = Md5::new();
-        let mut program_guid_array = [0; 16];
-        program_guid_hasher.input(data);
-        program_guid_hasher.result(&mut program_guid_array);
-        let program_guid = program_guid_array.to_vec();
-        self.append_data(&mut packet, &program_guid);
-
-        let footer = self.make_footer(&packet);
-        self.append_data(&mut packet, &footer);
-
-        self.append_data(&mut packet, &self.stop_bytes);
-
-        packet
+        let mut packet = [0; packet::CONTROL_PACKET_LEN];
+        self.packet_builder().control_packet(data, &mut packet);
+        packet.to_vec()
     }
 
     pub fn make_data_packet(&mut self, data_in: &[u8], block_num: u16) -> Vec<u8> {
-        let mut packet = vec![];
-        let mut data = data_in.to_owned();
-        let data_header = if self.os_update {
-            self.make_data_os_header(block_num)
-        } else {
-            self.make_data_header(block_num)
-        };
-        let data_header_len = data_header.len();
-        self.append_data(&mut packet, &data_header);
-
-        // Ensure the "data" payload is 256 bytes long.
-        data.resize(256, 0xff);
-        let data_len = data.len();
-        self.append_data(&mut packet, &data);
-
-        let footer = self.make_footer(&packet);
-        self.append_data(&mut packet, &footer);
-
-        // let stop_bytes = vec![0xff, 0xff];
-        self.append_data(&mut packet, &self.stop_bytes);
-
-        // After the hash has been computed, stripe the data portion
-        // with a pattern of 0x55 and 0xaa.  This provides some level
-        // of DC balance, even at the end where we have lots of 0xff.
-
-        match self.protocol_version {
-            ProtocolVersion::V1 => {
-                for i in 0..data_len {
-                    if (i % 16) == 3 {
-                        packet[i + data_header_len] ^= 0x55;
-                    } else if (i % 16) == 11 {
-                        packet[i + data_header_len] ^= 0xaa;
-                    }
-                }
-            }
-
-            ProtocolVersion::V2 => {
-                // modulate the packet # and payload
-                // so skip preamble + version + type (7 bytes preamble + 1 byte version + 1 byte type = 9)
-                // and then "add 2" in the modular math loop because on the demod side we are 2-offset
-                // also skip capping hash and stop bytes
-                let mod_range = (self.preamble.len() + 2)..(packet.len() - (4 + self.stop_bytes.len()));
-                for i in mod_range {
-                    if ((i - 9 + 2) % 3) == 0 {
-                        packet[i] ^= 0x35;
-                    } else if ((i - 9 + 2) % 3) == 1 {
-                        packet[i] ^= 0xac;
-                    } else if ((i - 9 + 2) % 3) == 2 {
-                        packet[i] ^= 0x95;
-                    }
-                }
-            }
-        }
-
-        packet
+        let mut packet = [0; packet::DATA_PACKET_LEN];
+        self.packet_builder().data_packet(data_in, block_num, &mut packet);
+        packet.to_vec()
     }
 
     pub fn make_silence(&mut self, msecs: u32) -> Vec<f64> {
@@ -235,11 +135,40 @@ impl Controller {
         buffer
     }
 
-    pub fn pilot(&mut self, output: &mut Vec<f64>, rate: &EncodingRate) {
-        if *rate == EncodingRate::Low {
-            let data = self.make_zero(4000); // ~0.5secs
-            let mut audio = self.modulator.modulate_pcm(&data);
-            output.append(&mut audio);
+    // Modulate a packet and set its level.  The fades go on carrier sent
+    // either side of the packet, so the packet itself is always at full
+    // level.  Fade lengths are stretched the same way silence is.
+    pub fn modulate_burst(&mut self, data: &[u8], stretch: u32) -> Vec<f64> {
+        let rate = self.rate;
+        let ms_to_samples = |ms: f64| (rate * ms / 1000.0 * f64::from(stretch)).round() as usize;
+        let fade_in = ms_to_samples(self.envelope.fade_in_ms);
+        let fade_out = ms_to_samples(self.envelope.fade_out_ms);
+
+        let mut audio = self.modulator.carrier(fade_in, true);
+        for (i, sample) in audio.iter_mut().enumerate() {
+            *sample *= raised_cosine(i, fade_in);
+        }
+        audio.append(&mut self.modulator.modulate_pcm(data));
+        let lead_out = self.modulator.carrier(fade_out, false);
+        audio.extend(
+            lead_out
+                .iter()
+                .enumerate()
+                .map(|(i, sample)| sample * raised_cosine(fade_out - 1 - i, fade_out)),
+        );
+
+        for sample in audio.iter_mut() {
+            *sample *= self.envelope.gain;
+        }
+        audio
+    }
+
+    pub fn pilot<E, F: FnMut(&[f64]) -> Result<(), E>>(&mut self, sink: &mut F, rate: &EncodingRate) -> Result<(), E> {
+        // Only slow transmissions need a pilot tone
+        if rate.stretch() >= PILOT_MIN_STRETCH {
+            let data = self.make_zero(PILOT_BITS); // ~0.5secs, times the stretch
+            let audio = self.modulate_burst(&data, rate.stretch());
+            self.append(sink, audio, Segment::Pilot)?;
         } else {
             // // no preamble at high rate, this is the default
             // let data = self.make_one(3000); // ~0.5secs
@@ -247,32 +176,105 @@ impl Controller {
             // output.append(&mut audio);
             //
         }
+        Ok(())
+    }
+
+    /// Where each packet, gap and repeat went in the output so far
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    // Hand a piece of audio to the sink, noting where it went
+    fn append<E, F: FnMut(&[f64]) -> Result<(), E>>(&mut self, sink: &mut F, audio: Vec<f64>, segment: Segment) -> Result<(), E> {
+        self.markers.push(Marker {
+            start: self.position,
+            length: audio.len(),
+            segment,
+        });
+        self.position += audio.len();
+        sink(&audio)
+    }
+
+    /// Produce a complete transmission: the program sent `repeat_count`
+    /// times, each followed by a pilot tone if the rate needs one.  The
+    /// markers start afresh with each transmission.
+    pub fn transmit(&mut self, input: &[u8], rate: &EncodingRate, repeat_count: u32) -> Vec<f64> {
+        let mut output: Vec<f64> = vec![];
+        let result: Result<(), std::convert::Infallible> = self.transmit_to(input, rate, repeat_count, |audio| {
+            output.extend_from_slice(audio);
+            Ok(())
+        });
+        match result {
+            Ok(()) => output,
+            Err(e) => match e {},
+        }
     }
 
-    pub fn encode(&mut self, input: &[u8], output: &mut Vec<f64>, rate: &EncodingRate) {
-        let silence_divisor = rate.silence_divisor();
+    /// Produce the same transmission as `transmit()`, handing each burst
+    /// and gap to `sink` as soon as it is modulated, so that long or
+    /// repeated transmissions never have to be held in memory.  Stops at
+    /// the first error from `sink`.
+    pub fn transmit_to<E, F>(&mut self, input: &[u8], rate: &EncodingRate, repeat_count: u32, mut sink: F) -> Result<(), E>
+    where
+        F: FnMut(&[f64]) -> Result<(), E>,
+    {
+        self.markers.clear();
+        self.position = 0;
+        for repeat in 0..repeat_count {
+            let start = self.position;
+            let first_marker = self.markers.len();
+            self.encode(input, &mut sink, rate)?;
+            self.pilot(&mut sink, rate)?;
+            let marker = Marker {
+                start,
+                length: self.position - start,
+                segment: Segment::Repeat(repeat + 1),
+            };
+            self.markers.insert(first_marker, marker);
+        }
+        Ok(())
+    }
+
+    /// Roughly how long `transmit()` takes, in seconds, for a program of
+    /// `length` bytes, given how long one byte takes to send at full speed
+    pub fn estimate_duration(length: usize, rate: &EncodingRate, repeat_count: u32, seconds_per_byte: f64) -> f64 {
+        let stretch = f64::from(rate.stretch());
+        let blocks = (length as f64 / 256.0).ceil();
+
+        let silence_ms = f64::from(LEAD_IN_MS + CONTROL_GAP_MS + DATA_START_MS + TRAILER_MS)
+            + blocks * f64::from(DATA_GAP_MS);
+        let mut bytes = 2.0 * packet::CONTROL_PACKET_LEN as f64 + blocks * packet::DATA_PACKET_LEN as f64;
+        if rate.stretch() >= PILOT_MIN_STRETCH {
+            bytes += f64::from(PILOT_BITS / 8);
+        }
+
+        f64::from(repeat_count) * stretch * (silence_ms / 1000.0 + bytes * seconds_per_byte)
+    }
+
+    pub fn encode<E, F: FnMut(&[f64]) -> Result<(), E>>(&mut self, input: &[u8], sink: &mut F, rate: &EncodingRate) -> Result<(), E> {
+        let stretch = rate.stretch();
         let file_length = input.len();
 
         // Note: Maximum of 65536 blocks
         let blocks = ((file_length as f64 / 256.0).ceil()) as u16;
 
-        let mut audio = self.make_silence(250 / silence_divisor);
-        output.append(&mut audio);
+        let audio = self.make_silence(LEAD_IN_MS * stretch);
+        self.append(sink, audio, Segment::Silence)?;
 
         let data = self.make_control_packet(&input);
-        let mut audio = self.modulator.modulate_pcm(&data);
-        output.append(&mut audio);
+        let audio = self.modulate_burst(&data, stretch);
+        self.append(sink, audio, Segment::ControlPacket)?;
 
-        let mut audio = self.make_silence(100 / silence_divisor);
-        output.append(&mut audio);
+        let audio = self.make_silence(CONTROL_GAP_MS * stretch);
+        self.append(sink, audio, Segment::Silence)?;
 
         // Make two header packets
         let data = self.make_control_packet(&input);
-        let mut audio = self.modulator.modulate_pcm(&data);
-        output.append(&mut audio);
+        let audio = self.modulate_burst(&data, stretch);
+        self.append(sink, audio, Segment::ControlPacket)?;
 
-        let mut audio = self.make_silence(500 / silence_divisor);
-        output.append(&mut audio);
+        let audio = self.make_silence(DATA_START_MS * stretch);
+        self.append(sink, audio, Segment::Silence)?;
 
         for mut packet_num in 0..blocks {
             packet_num &= 0xff;
@@ -287,14 +289,143 @@ impl Controller {
                 }
             }
             let data = self.make_data_packet(&packet_data, packet_num as u16);
-            let mut audio = self.modulator.modulate_pcm(&data);
-            output.append(&mut audio);
+            let audio = self.modulate_burst(&data, stretch);
+            self.append(sink, audio, Segment::DataPacket(packet_num))?;
 
-            let mut audio = self.make_silence(80 / silence_divisor);
-            output.append(&mut audio);
+            let audio = self.make_silence(DATA_GAP_MS * stretch);
+            self.append(sink, audio, Segment::Silence)?;
         }
 
-        let mut audio = self.make_silence(500 / silence_divisor);
-        output.append(&mut audio);
+        let audio = self.make_silence(TRAILER_MS * stretch);
+        self.append(sink, audio, Segment::Silence)
+    }
+}
+
+// Rising half of a raised-cosine window, `length` samples long
+fn raised_cosine(pos: usize, length: usize) -> f64 {
+    0.5 * (1.0 - (std::f64::consts::PI * (pos as f64 + 0.5) / length as f64).cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fsk::{Framing, FskEncoder};
+
+    fn encoder() -> FskEncoder {
+        FskEncoder::new(&[8666.0, 12500.0], 1200.0, 48000.0, Framing::Raw, None)
+    }
+
+    fn make_controller(envelope: Envelope) -> Controller {
+        Controller::new(48000.0, false, ProtocolVersion::V2, Box::new(encoder()), envelope)
+    }
+
+    #[test]
+    fn fades_leave_packet_alone() {
+        let envelope = Envelope {
+            fade_in_ms: 5.0,
+            fade_out_ms: 10.0,
+            gain: 0.5,
+        };
+        let mut controller = make_controller(envelope);
+        let data = controller.make_control_packet(&[0x55; 300]);
+        let burst = controller.modulate_burst(&data, 1);
+
+        let mut reference = encoder();
+        let lead_in = reference.carrier(240, true);
+        let packet = reference.modulate(&data);
+        let lead_out = reference.carrier(480, false);
+        assert_eq!(burst.len(), lead_in.len() + packet.len() + lead_out.len());
+
+        let (faded_in, rest) = burst.split_at(240);
+        let (sent, faded_out) = rest.split_at(packet.len());
+        for (sample, expected) in sent.iter().zip(&packet) {
+            assert_eq!(*sample, expected * 0.5);
+        }
+        assert!(faded_in[0].abs() < 0.001);
+        assert!(faded_out[479].abs() < 0.001);
+        for (i, (sample, carrier)) in faded_in.iter().zip(&lead_in).enumerate() {
+            assert_eq!(*sample, carrier * raised_cosine(i, 240) * 0.5);
+        }
+    }
+
+    #[test]
+    fn pilot_threshold() {
+        let has_pilot = |stretch: u32| {
+            let mut controller = make_controller(Envelope::none());
+            let rate = EncodingRate::parse(&stretch.to_string()).unwrap();
+            controller.transmit(&[0; 16], &rate, 1);
+            controller.markers().iter().any(|m| m.segment == Segment::Pilot)
+        };
+        assert!(!has_pilot(PILOT_MIN_STRETCH - 1));
+        assert!(has_pilot(PILOT_MIN_STRETCH));
+    }
+
+    #[test]
+    fn markers_start_afresh() {
+        let mut controller = make_controller(Envelope::none());
+        let output = controller.transmit(&[0; 600], &EncodingRate::HIGH, 2);
+        let first: Vec<Segment> = controller.markers().iter().map(|m| m.segment).collect();
+        assert_eq!(first[0], Segment::Repeat(1));
+        assert_eq!(first.iter().filter(|s| **s == Segment::DataPacket(2)).count(), 2);
+        let last = controller.markers().last().unwrap();
+        assert_eq!(last.start + last.length, output.len());
+
+        controller.transmit(&[0; 600], &EncodingRate::HIGH, 2);
+        let second: Vec<Segment> = controller.markers().iter().map(|m| m.segment).collect();
+        assert_eq!(first, second);
+        assert_eq!(controller.markers()[0].start, 0);
+    }
+
+    #[test]
+    fn streams_a_burst_at_a_time() {
+        let envelope = Envelope {
+            fade_in_ms: 2.0,
+            fade_out_ms: 2.0,
+            gain: 0.8,
+        };
+        let mut controller = make_controller(envelope);
+        let whole = controller.transmit(&[0x5a; 600], &EncodingRate::HIGH, 2);
+
+        let mut controller = make_controller(envelope);
+        let mut pieces: Vec<Vec<f64>> = vec![];
+        let result: Result<(), ()> = controller.transmit_to(&[0x5a; 600], &EncodingRate::HIGH, 2, |audio| {
+            pieces.push(audio.to_vec());
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(pieces.concat(), whole);
+
+        // One piece for every segment, and nothing held back for the repeats
+        let segments = controller
+            .markers()
+            .iter()
+            .filter(|m| !matches!(m.segment, Segment::Repeat(_)));
+        for (piece, marker) in pieces.iter().zip(segments) {
+            assert_eq!(piece.len(), marker.length);
+        }
+        assert_eq!(pieces.len(), controller.markers().len() - 2);
+    }
+
+    #[test]
+    fn sink_errors_stop_the_transmission() {
+        let mut controller = make_controller(Envelope::none());
+        let mut calls = 0;
+        let result = controller.transmit_to(&[0; 600], &EncodingRate::HIGH, 3, |_| {
+            calls += 1;
+            if calls == 4 {
+                Err("full")
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err("full"));
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn no_fades() {
+        let mut controller = make_controller(Envelope::none());
+        let data = controller.make_control_packet(&[0x55; 300]);
+        assert_eq!(controller.modulate_burst(&data, 1), encoder().modulate(&data));
     }
 }
@@ -0,0 +1,75 @@
+use controller;
+use fsk;
+use preset;
+use validate;
+use EncodingRate;
+use DEFAULT_SAMPLE_RATE;
+
+/// FSK upload settings for programs that embed the modulator, such as the
+/// JavaScript and C bindings.  A new `Encoder` matches the command-line
+/// defaults.
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    pub protocol_version: controller::ProtocolVersion,
+    pub rate: EncodingRate,
+    pub os_update: bool,
+    pub baud_rate: f64,
+    pub tones: Vec<f64>,
+    pub framing: fsk::Framing,
+    pub repeat_count: u32,
+    pub sample_rate: f64,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        let mut encoder = Encoder {
+            protocol_version: controller::ProtocolVersion::V2,
+            rate: EncodingRate::HIGH,
+            os_update: false,
+            baud_rate: 0.0,
+            tones: vec![],
+            framing: fsk::Framing::Raw,
+            repeat_count: 3,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        };
+        encoder.use_preset(preset::find(preset::DEFAULT_PRESET).unwrap());
+        encoder
+    }
+
+    /// Take the baud rate, tones and framing from a modem preset
+    pub fn use_preset(&mut self, preset: &preset::Preset) {
+        self.baud_rate = preset.baud_rate;
+        self.tones = vec![preset.f_lo, preset.f_hi];
+        self.framing = preset.framing;
+    }
+
+    /// Modulate a program into samples in the range -1.0 .. 1.0
+    pub fn transmit(&self, program: &[u8]) -> Result<Vec<f64>, validate::ValidationError> {
+        let modulation_rate = self.rate.modulation_rate(self.sample_rate);
+        validate::check_fsk(modulation_rate, self.baud_rate, &self.tones)?;
+
+        let modulator = fsk::FskEncoder::new(&self.tones, self.baud_rate, modulation_rate, self.framing, None);
+        let mut controller = controller::Controller::new(
+            self.sample_rate,
+            self.os_update,
+            self.protocol_version,
+            Box::new(modulator),
+            controller::Envelope::none(),
+        );
+        Ok(controller.transmit(program, &self.rate, self.repeat_count))
+    }
+
+    /// Roughly how long a program of `length` bytes takes to send, in seconds
+    pub fn estimated_duration(&self, length: usize) -> f64 {
+        let bits_per_symbol = self.tones.len().trailing_zeros().max(1);
+        let seconds_per_byte =
+            f64::from(self.framing.bits_per_byte()) / (self.baud_rate * f64::from(bits_per_symbol));
+        controller::Controller::estimate_duration(length, &self.rate, self.repeat_count, seconds_per_byte)
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
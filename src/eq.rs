@@ -0,0 +1,347 @@
+use std;
+use std::f64;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+
+/// Device classes with a built-in correction curve
+pub const PRESET_NAMES: [&str; 3] = ["phone", "tablet", "laptop"];
+
+// Number of taps used when designing a filter from a measured response
+const FIR_TAPS: usize = 255;
+
+// Number of points used to find the peak gain of a filter
+const RESPONSE_POINTS: usize = 512;
+
+/// A single second-order section, from the RBJ audio EQ cookbook
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn from_coefficients(b: [f64; 3], a: [f64; 3]) -> Biquad {
+        Biquad {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    pub fn peaking(sample_rate: f64, freq: f64, q: f64, gain_db: f64) -> Biquad {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * f64::consts::PI * freq / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        Biquad::from_coefficients(
+            [1.0 + alpha * a, -2.0 * w0.cos(), 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * w0.cos(), 1.0 - alpha / a],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: f64, freq: f64, q: f64, gain_db: f64) -> Biquad {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * f64::consts::PI * freq / sample_rate;
+        let cos = w0.cos();
+        let beta = 2.0 * a.sqrt() * w0.sin() / (2.0 * q);
+        Biquad::from_coefficients(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + beta),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + beta,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+
+    // Magnitude of the response at `w` radians per sample
+    fn response(&self, w: f64) -> f64 {
+        let (c1, s1, c2, s2) = (w.cos(), w.sin(), (2.0 * w).cos(), (2.0 * w).sin());
+        let num = (self.b0 + self.b1 * c1 + self.b2 * c2, -(self.b1 * s1 + self.b2 * s2));
+        let den = (1.0 + self.a1 * c1 + self.a2 * c2, -(self.a1 * s1 + self.a2 * s2));
+        ((num.0 * num.0 + num.1 * num.1) / (den.0 * den.0 + den.1 * den.1)).sqrt()
+    }
+}
+
+enum Filter {
+    Biquads(Vec<Biquad>),
+    Fir(Vec<f64>),
+}
+
+/// Speaker equalization applied to the modulated audio, so that every
+/// tone reaches the sticker at a similar level.  Filters are normalised
+/// to a peak gain of 0 dB, which turns boosts into cuts elsewhere, so the
+/// steady-state gain of any single tone is at most 0 dB.  Transients, such
+/// as the start of a burst, can still overshoot.
+pub struct Equalizer {
+    filter: Filter,
+    scale: f64,
+
+    // Input the FIR filter still needs, starting with the samples before
+    // the next output sample
+    history: Vec<f64>,
+}
+
+impl Equalizer {
+    fn new(filter: Filter) -> Equalizer {
+        let peak = (0..RESPONSE_POINTS)
+            .map(|i| {
+                let w = f64::consts::PI * i as f64 / RESPONSE_POINTS as f64;
+                match filter {
+                    Filter::Biquads(ref stages) => stages.iter().map(|s| s.response(w)).product(),
+                    Filter::Fir(ref taps) => fir_response(taps, w),
+                }
+            })
+            .fold(0.0, f64::max);
+        // Start with silence ahead of the first sample, so the taps can
+        // be centred on it
+        let history = match filter {
+            Filter::Biquads(_) => vec![],
+            Filter::Fir(ref taps) => vec![0.0; taps.len() - 1 - taps.len() / 2],
+        };
+        Equalizer {
+            filter,
+            scale: 1.0 / peak,
+            history,
+        }
+    }
+
+    /// Built-in correction for a class of device, one of `PRESET_NAMES`.
+    /// These are starting points for small speakers that roll off across
+    /// the 8-13 kHz band, not measurements of any particular model.
+    pub fn preset(name: &str, sample_rate: f64) -> Option<Equalizer> {
+        let stages = match name {
+            "phone" => vec![Biquad::high_shelf(sample_rate, 9000.0, 0.707, 9.0)],
+            "tablet" => vec![Biquad::high_shelf(sample_rate, 10000.0, 0.707, 5.0)],
+            "laptop" => vec![Biquad::peaking(sample_rate, 12500.0, 1.0, 4.0)],
+            _ => return None,
+        };
+        Some(Equalizer::new(Filter::Biquads(stages)))
+    }
+
+    /// Design a correction filter from a measured speaker response.  The
+    /// file has one "frequency_hz gain_db" pair per line, in increasing
+    /// order of frequency.  Blank lines and lines starting with '#' are
+    /// ignored.
+    pub fn from_response_file(filename: &str, sample_rate: f64) -> std::io::Result<Equalizer> {
+        let mut contents = String::new();
+        File::open(filename)?.read_to_string(&mut contents)?;
+
+        let mut points: Vec<(f64, f64)> = vec![];
+        for (line_num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: expected \"frequency_hz gain_db\"", filename, line_num + 1),
+                )
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(bad_line());
+            }
+            let freq = fields[0].parse::<f64>().map_err(|_| bad_line())?;
+            let gain = fields[1].parse::<f64>().map_err(|_| bad_line())?;
+            if let Some(&(last_freq, _)) = points.last() {
+                if freq <= last_freq {
+                    return Err(bad_line());
+                }
+            }
+            points.push((freq, gain));
+        }
+        if points.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: no response points found", filename),
+            ));
+        }
+
+        // Frequency-sampling design of a linear-phase FIR that applies
+        // the inverse of the measured response, windowed to tame ripple.
+        let mid = FIR_TAPS / 2;
+        let amplitudes: Vec<f64> = (0..=mid)
+            .map(|k| {
+                let freq = k as f64 * sample_rate / FIR_TAPS as f64;
+                10f64.powf(-interpolate(&points, freq) / 20.0)
+            })
+            .collect();
+        let taps = (0..FIR_TAPS)
+            .map(|n| {
+                let offset = n as f64 - mid as f64;
+                let mut sum = amplitudes[0];
+                for (k, amplitude) in amplitudes.iter().enumerate().skip(1) {
+                    sum += 2.0 * amplitude * (2.0 * f64::consts::PI * k as f64 * offset / FIR_TAPS as f64).cos();
+                }
+                let window = 0.5 - 0.5 * (2.0 * f64::consts::PI * n as f64 / (FIR_TAPS - 1) as f64).cos();
+                sum / FIR_TAPS as f64 * window
+            })
+            .collect();
+        Ok(Equalizer::new(Filter::Fir(taps)))
+    }
+
+    /// Filter the next piece of the audio.  The FIR filter looks half
+    /// its length ahead, so it holds that much back until `finish`.
+    pub fn process(&mut self, samples: &[f64]) -> Vec<f64> {
+        let scale = self.scale;
+        match self.filter {
+            Filter::Biquads(ref mut stages) => samples
+                .iter()
+                .map(|sample| {
+                    let mut value = *sample;
+                    for stage in stages.iter_mut() {
+                        value = stage.process(value);
+                    }
+                    value * scale
+                })
+                .collect(),
+            Filter::Fir(ref taps) => {
+                // Centre the taps on each output sample, which removes
+                // the filter's group delay.
+                self.history.extend_from_slice(samples);
+                if self.history.len() < taps.len() {
+                    return vec![];
+                }
+                let ready = self.history.len() - taps.len() + 1;
+                let mut output = Vec::with_capacity(ready);
+                for window in self.history.windows(taps.len()) {
+                    let mut sum = 0.0;
+                    for (k, tap) in taps.iter().enumerate() {
+                        sum += tap * window[taps.len() - 1 - k];
+                    }
+                    output.push(sum * scale);
+                }
+                self.history.drain(..ready);
+                output
+            }
+        }
+    }
+
+    /// Filter whatever is still held back, as if the audio were
+    /// followed by silence
+    pub fn finish(&mut self) -> Vec<f64> {
+        let lookahead = match self.filter {
+            Filter::Biquads(_) => return vec![],
+            Filter::Fir(ref taps) => taps.len() / 2,
+        };
+        self.process(&vec![0.0; lookahead])
+    }
+}
+
+// Magnitude of an FIR filter's response at `w` radians per sample
+fn fir_response(taps: &[f64], w: f64) -> f64 {
+    let (re, im) = taps.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, tap)| {
+        (re + tap * (w * n as f64).cos(), im - tap * (w * n as f64).sin())
+    });
+    (re * re + im * im).sqrt()
+}
+
+// Linearly interpolate the gain at `freq`, holding the end values
+fn interpolate(points: &[(f64, f64)], freq: f64) -> f64 {
+    if freq <= points[0].0 {
+        return points[0].1;
+    }
+    for pair in points.windows(2) {
+        let (f0, g0) = pair[0];
+        let (f1, g1) = pair[1];
+        if freq <= f1 {
+            return g0 + (g1 - g0) * (freq - f0) / (f1 - f0);
+        }
+    }
+    points[points.len() - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_signal() -> Vec<f64> {
+        (0..2000).map(|n| (n as f64 * 0.37).sin() * (n as f64 * 0.011).cos()).collect()
+    }
+
+    fn fir() -> Equalizer {
+        let taps = (0..31).map(|n| 1.0 / (1.0 + (n as f64 - 15.0).abs())).collect();
+        Equalizer::new(Filter::Fir(taps))
+    }
+
+    // Feed the signal through in uneven pieces
+    fn chunked(mut equalizer: Equalizer, input: &[f64]) -> Vec<f64> {
+        let mut output = vec![];
+        for piece in input.chunks(7) {
+            output.extend(equalizer.process(piece));
+        }
+        output.extend(equalizer.finish());
+        output
+    }
+
+    #[test]
+    fn fir_is_centred() {
+        let input = test_signal();
+        let mut equalizer = fir();
+        let (taps, scale) = match equalizer.filter {
+            Filter::Fir(ref taps) => (taps.clone(), equalizer.scale),
+            _ => unreachable!(),
+        };
+        let mut output = equalizer.process(&input);
+        output.extend(equalizer.finish());
+        assert_eq!(output.len(), input.len());
+
+        let mid = taps.len() / 2;
+        for (n, sample) in output.iter().enumerate() {
+            let mut expected = 0.0;
+            for (k, tap) in taps.iter().enumerate() {
+                if n + mid >= k && n + mid - k < input.len() {
+                    expected += tap * input[n + mid - k];
+                }
+            }
+            assert!((sample - expected * scale).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn pieces_match_whole() {
+        let input = test_signal();
+        for make in &[fir as fn() -> Equalizer, || Equalizer::preset("phone", 48000.0).unwrap()] {
+            let mut whole = make();
+            let mut expected = whole.process(&input);
+            expected.extend(whole.finish());
+            assert_eq!(chunked(make(), &input), expected);
+        }
+    }
+
+    #[test]
+    fn short_input() {
+        let mut equalizer = fir();
+        assert!(equalizer.process(&[0.5; 3]).is_empty());
+        assert_eq!(equalizer.finish().len(), 3);
+    }
+}
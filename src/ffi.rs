@@ -0,0 +1,207 @@
+//! C bindings, built with the `ffi` feature:
+//!
+//! ```text
+//! cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib
+//! ```
+//!
+//! The functions are declared in `include/ltc_modulate.h`, which also
+//! describes what each pointer argument must point to.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr;
+use std::slice;
+
+use controller;
+use encoder::Encoder;
+use preset;
+use validate;
+use wav;
+use EncodingRate;
+
+pub const LTC_OK: c_int = 0;
+pub const LTC_ERR_INVALID_ARGUMENT: c_int = -1;
+pub const LTC_ERR_INVALID_SETTINGS: c_int = -2;
+pub const LTC_ERR_IO: c_int = -3;
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+unsafe fn to_slice<'a, T>(data: *const T, len: usize) -> Option<&'a [T]> {
+    if len == 0 {
+        Some(&[])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(data, len))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ltc_encoder_new() -> *mut Encoder {
+    Box::into_raw(Box::new(Encoder::new()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_free(encoder: *mut Encoder) {
+    if !encoder.is_null() {
+        drop(Box::from_raw(encoder));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_preset(encoder: *mut Encoder, name: *const c_char) -> c_int {
+    let (encoder, name) = match (encoder.as_mut(), to_str(name)) {
+        (Some(e), Some(n)) => (e, n),
+        _ => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    match preset::find(name) {
+        Some(p) => encoder.use_preset(p),
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    }
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_protocol_version(encoder: *mut Encoder, version: c_uint) -> c_int {
+    let encoder = match encoder.as_mut() {
+        Some(e) => e,
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    encoder.protocol_version = match version {
+        1 => controller::ProtocolVersion::V1,
+        2 => controller::ProtocolVersion::V2,
+        _ => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_encoding_rate(encoder: *mut Encoder, rate: *const c_char) -> c_int {
+    let (encoder, rate) = match (encoder.as_mut(), to_str(rate)) {
+        (Some(e), Some(r)) => (e, r),
+        _ => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    encoder.rate = match EncodingRate::parse(rate) {
+        Some(rate) => rate,
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_os_update(encoder: *mut Encoder, os_update: c_int) -> c_int {
+    match encoder.as_mut() {
+        Some(e) => e.os_update = os_update != 0,
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    }
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_baud_rate(encoder: *mut Encoder, baud_rate: f64) -> c_int {
+    match encoder.as_mut() {
+        Some(e) => e.baud_rate = baud_rate,
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    }
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_tones(encoder: *mut Encoder, tones: *const f64, count: usize) -> c_int {
+    let (encoder, tones) = match (encoder.as_mut(), to_slice(tones, count)) {
+        (Some(e), Some(t)) => (e, t),
+        _ => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    if tones.len() < 2 || !tones.len().is_power_of_two() || tones.len() > validate::MAX_FSK_TONES {
+        return LTC_ERR_INVALID_ARGUMENT;
+    }
+    encoder.tones = tones.to_vec();
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_repeat_count(encoder: *mut Encoder, repeat_count: u32) -> c_int {
+    match encoder.as_mut() {
+        Some(e) => e.repeat_count = repeat_count,
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    }
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encoder_set_sample_rate(encoder: *mut Encoder, sample_rate: f64) -> c_int {
+    match encoder.as_mut() {
+        Some(e) => e.sample_rate = sample_rate,
+        None => return LTC_ERR_INVALID_ARGUMENT,
+    }
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_encode_pcm(
+    encoder: *const Encoder,
+    program: *const u8,
+    program_len: usize,
+    samples: *mut *mut f32,
+    sample_count: *mut usize,
+) -> c_int {
+    let (encoder, program) = match (encoder.as_ref(), to_slice(program, program_len)) {
+        (Some(e), Some(p)) => (e, p),
+        _ => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    if samples.is_null() || sample_count.is_null() {
+        return LTC_ERR_INVALID_ARGUMENT;
+    }
+    let audio = match encoder.transmit(program) {
+        Ok(audio) => audio,
+        Err(_) => return LTC_ERR_INVALID_SETTINGS,
+    };
+
+    let audio: Box<[f32]> = audio.iter().map(|s| *s as f32).collect();
+    *sample_count = audio.len();
+    *samples = Box::into_raw(audio) as *mut f32;
+    LTC_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_pcm_free(samples: *mut f32, sample_count: usize) {
+    if !samples.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(samples, sample_count)));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_write_wav(
+    encoder: *const Encoder,
+    program: *const u8,
+    program_len: usize,
+    filename: *const c_char,
+) -> c_int {
+    let (encoder, program, filename) = match (encoder.as_ref(), to_slice(program, program_len), to_str(filename)) {
+        (Some(e), Some(p), Some(f)) => (e, p, f),
+        _ => return LTC_ERR_INVALID_ARGUMENT,
+    };
+    let audio = match encoder.transmit(program) {
+        Ok(audio) => audio,
+        Err(_) => return LTC_ERR_INVALID_SETTINGS,
+    };
+    match wav::write_wav(encoder.sample_rate as u32, &audio, &wav::WavOptions::default(), filename) {
+        Ok(()) => LTC_OK,
+        Err(_) => LTC_ERR_IO,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ltc_estimated_duration(encoder: *const Encoder, program_len: usize) -> f64 {
+    match encoder.as_ref() {
+        Some(e) => e.estimated_duration(program_len),
+        None => -1.0,
+    }
+}
@@ -0,0 +1,743 @@
+extern crate byteorder;
+use std;
+use std::io::prelude::*;
+use std::fs::File;
+use self::byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+use ltc_core::md5;
+use wav::{SampleFormat, WavOptions};
+
+// Samples per channel in every frame but the last
+const BLOCK_SIZE: usize = 4096;
+
+// Smallest block size STREAMINFO can declare.  Only the last block may
+// be shorter.
+const MIN_BLOCK_SIZE: usize = 16;
+
+// Highest fixed predictor order, and the most partitions the residual is
+// split into (as a power of two)
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+
+// Rice parameters that fit in the 4 and 5-bit fields.  The all-ones
+// value is reserved as an escape code.
+const MAX_RICE4_PARAMETER: u32 = 14;
+const MAX_RICE5_PARAMETER: u32 = 30;
+
+// Metadata block types
+const BLOCK_STREAMINFO: u8 = 0;
+const BLOCK_VORBIS_COMMENT: u8 = 4;
+
+// Channel assignment that stores the left channel and the difference
+// between the channels.  Lower values mean that many channels, less one,
+// stored independently.
+const CHANNELS_LEFT_SIDE: u64 = 8;
+
+const VENDOR: &str = "ltc-modulate";
+
+/// Write a FLAC file using the same options as `wav::write_wav`.  FLAC
+/// only stores integer samples, so `S32` and `F32` are rejected, and `U8`
+/// becomes signed 8-bit.  Cue points are not written.
+pub fn write_flac(rate: u32, samples: &[f64], options: &WavOptions, filename: &str) -> std::io::Result<()> {
+    let mut file = File::create(filename)?;
+    write_flac_to(rate, samples, options, &mut file)
+}
+
+/// Write a FLAC file to anything that implements `Write`.  Samples are in
+/// the range -1.0 .. 1.0.
+pub fn write_flac_to<W: Write>(rate: u32, samples: &[f64], options: &WavOptions, file: &mut W) -> std::io::Result<()> {
+    let format = options.format;
+    let layout = options.layout;
+    let bits_per_sample = u32::from(format.bits_per_sample());
+    // Frame header code for each sample size
+    let sample_size_code = match format {
+        SampleFormat::U8 => 1,
+        SampleFormat::S16 => 4,
+        SampleFormat::S24 => 6,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "FLAC can only store u8, s16 or s24 samples",
+            ))
+        }
+    };
+    if rate == 0 || rate >= 1 << 20 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("FLAC cannot store a sample rate of {}", rate),
+        ));
+    }
+
+    let num_channels = usize::from(layout.channels());
+    let mut channels = vec![Vec::with_capacity(samples.len()); num_channels];
+    for sample in samples {
+        for (channel, output) in channels.iter_mut().enumerate() {
+            output.push(format.quantize(layout.channel_value(*sample, channel)).unwrap());
+        }
+    }
+
+    // The MD5 signature covers the interleaved samples, little-endian
+    let bytes_per_sample = bits_per_sample as usize / 8;
+    let mut signature_data = Vec::with_capacity(samples.len() * num_channels * bytes_per_sample);
+    for i in 0..samples.len() {
+        for channel in &channels {
+            signature_data.extend_from_slice(&channel[i].to_le_bytes()[..bytes_per_sample]);
+        }
+    }
+
+    let mut frames = vec![];
+    let mut min_frame_len = u32::MAX;
+    let mut max_frame_len = 0;
+    for (frame_number, start) in (0..samples.len()).step_by(BLOCK_SIZE).enumerate() {
+        let end = (start + BLOCK_SIZE).min(samples.len());
+        let block: Vec<&[i32]> = channels.iter().map(|c| &c[start..end]).collect();
+        let frame = encode_frame(frame_number as u64, &block, bits_per_sample, sample_size_code);
+        min_frame_len = min_frame_len.min(frame.len() as u32);
+        max_frame_len = max_frame_len.max(frame.len() as u32);
+        frames.extend(frame);
+    }
+    if frames.is_empty() {
+        min_frame_len = 0;
+    }
+
+    // A stream shorter than a block is a single, short last block, but
+    // the sizes in STREAMINFO can't go below 16
+    let block_size = BLOCK_SIZE.min(samples.len()).max(MIN_BLOCK_SIZE) as u64;
+    let mut streaminfo = BitWriter::new();
+    /* minBlockSize */  streaminfo.write(block_size, 16);
+    /* maxBlockSize */  streaminfo.write(block_size, 16);
+    /* minFrameSize */  streaminfo.write(u64::from(min_frame_len), 24);
+    /* maxFrameSize */  streaminfo.write(u64::from(max_frame_len), 24);
+    /* sampleRate */    streaminfo.write(u64::from(rate), 20);
+    /* channels */      streaminfo.write(num_channels as u64 - 1, 3);
+    /* bitsPerSample */ streaminfo.write(u64::from(bits_per_sample) - 1, 5);
+    /* totalSamples */  streaminfo.write(samples.len() as u64, 36);
+    let mut streaminfo = streaminfo.into_bytes();
+    /* md5 */           streaminfo.extend_from_slice(&md5::digest(&signature_data));
+
+    file.write_all(b"fLaC")?;
+    write_metadata_block(file, BLOCK_STREAMINFO, options.info.is_empty(), &streaminfo)?;
+    if !options.info.is_empty() {
+        write_metadata_block(file, BLOCK_VORBIS_COMMENT, true, &make_vorbis_comment(&options.info)?)?;
+    }
+    file.write_all(&frames)?;
+
+    Ok(())
+}
+
+fn write_metadata_block<W: Write>(file: &mut W, block_type: u8, last: bool, data: &[u8]) -> std::io::Result<()> {
+    file.write_u8(if last { 0x80 | block_type } else { block_type })?;
+    file.write_u24::<BigEndian>(data.len() as u32)?;
+    file.write_all(data)
+}
+
+// Carry the WAV INFO tags over as Vorbis comments, using the usual field
+// names for the tags that have one
+fn make_vorbis_comment(info: &[([u8; 4], String)]) -> std::io::Result<Vec<u8>> {
+    let mut block = vec![];
+    block.write_u32::<LittleEndian>(VENDOR.len() as u32)?;
+    block.write_all(VENDOR.as_bytes())?;
+    block.write_u32::<LittleEndian>(info.len() as u32)?;
+    for (id, text) in info {
+        let field = match id {
+            b"INAM" => "TITLE".to_owned(),
+            b"ICMT" => "COMMENT".to_owned(),
+            b"ISFT" => "ENCODER".to_owned(),
+            _ => String::from_utf8_lossy(id).into_owned(),
+        };
+        let comment = format!("{}={}", field, text);
+        block.write_u32::<LittleEndian>(comment.len() as u32)?;
+        block.write_all(comment.as_bytes())?;
+    }
+    Ok(block)
+}
+
+// Encode one frame holding the same samples from each channel
+fn encode_frame(frame_number: u64, block: &[&[i32]], bits_per_sample: u32, sample_size_code: u64) -> Vec<u8> {
+    let block_len = block[0].len();
+    let mut w = BitWriter::new();
+
+    // Identical channels are stored as the left channel and a silent
+    // difference, which costs next to nothing
+    let left_side = block.len() == 2 && block[0] == block[1];
+
+    /* sync */          w.write(0xfff8, 16);    // fixed block size
+    /* blockSize */     w.write(if block_len == BLOCK_SIZE { 12 } else { 7 }, 4);
+    /* sampleRate */    w.write(0, 4);          // from STREAMINFO
+    /* channels */      w.write(if left_side {
+                            CHANNELS_LEFT_SIDE
+                        } else {
+                            block.len() as u64 - 1
+                        }, 4);
+    /* sampleSize */    w.write(sample_size_code, 3);
+    /* reserved */      w.write(0, 1);
+    /* frameNumber */   w.write_utf8(frame_number);
+    if block_len != BLOCK_SIZE {
+        /* blockSize */ w.write(block_len as u64 - 1, 16);
+    }
+    let crc = crc8(w.bytes());
+    /* crc8 */          w.write(u64::from(crc), 8);
+
+    if left_side {
+        encode_subframe(&mut w, block[0], bits_per_sample);
+        // The side channel needs one more bit
+        let side: Vec<i32> = block[0].iter().zip(block[1]).map(|(l, r)| l - r).collect();
+        encode_subframe(&mut w, &side, bits_per_sample + 1);
+    } else {
+        for channel in block {
+            encode_subframe(&mut w, channel, bits_per_sample);
+        }
+    }
+
+    w.align();
+    let crc = crc16(w.bytes());
+    /* crc16 */         w.write(u64::from(crc), 16);
+    w.into_bytes()
+}
+
+// Write a subframe as a constant if every sample matches, otherwise with
+// whichever fixed predictor, or none at all, takes the fewest bits
+fn encode_subframe(w: &mut BitWriter, samples: &[i32], bits_per_sample: u32) {
+    if samples.iter().all(|s| *s == samples[0]) {
+        /* type */      w.write(0, 8);
+        w.write_signed(i64::from(samples[0]), bits_per_sample);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * u64::from(bits_per_sample);
+    let mut best: Option<(u64, usize, Vec<i64>, RicePartitions)> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+        let residual = fixed_residual(samples, order);
+        let partitions = match choose_partitions(&residual, samples.len(), order) {
+            Some(p) => p,
+            None => continue,
+        };
+        let bits = order as u64 * u64::from(bits_per_sample) + partitions.bits;
+        if best.as_ref().map_or(true, |b| bits < b.0) {
+            best = Some((bits, order, residual, partitions));
+        }
+    }
+
+    match best {
+        Some((bits, order, residual, partitions)) if bits < verbatim_bits => {
+            /* type */  w.write(0x10 | (order as u64) << 1, 8);
+            for sample in &samples[..order] {
+                w.write_signed(i64::from(*sample), bits_per_sample);
+            }
+            write_residual(w, &residual, order, &partitions);
+        }
+        _ => {
+            /* type */  w.write(0x02, 8);
+            for sample in samples {
+                w.write_signed(i64::from(*sample), bits_per_sample);
+            }
+        }
+    }
+}
+
+// The error left after predicting each sample from the `order` before it
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i64> {
+    let s = |i: usize| i64::from(samples[i]);
+    (order..samples.len())
+        .map(|i| match order {
+            0 => s(i),
+            1 => s(i) - s(i - 1),
+            2 => s(i) - 2 * s(i - 1) + s(i - 2),
+            3 => s(i) - 3 * s(i - 1) + 3 * s(i - 2) - s(i - 3),
+            _ => s(i) - 4 * s(i - 1) + 6 * s(i - 2) - 4 * s(i - 3) + s(i - 4),
+        })
+        .collect()
+}
+
+// How the residual is split up, and the Rice parameter for each part
+struct RicePartitions {
+    order: u32,
+    parameters: Vec<u32>,
+    bits: u64,
+}
+
+// Try each partition order that divides the block evenly, and keep the
+// one that codes the residual in the fewest bits
+fn choose_partitions(residual: &[i64], block_len: usize, predictor_order: usize) -> Option<RicePartitions> {
+    let mut best: Option<RicePartitions> = None;
+    for order in 0..=MAX_PARTITION_ORDER {
+        let partition_len = block_len >> order;
+        if block_len % (1 << order) != 0 || partition_len <= predictor_order {
+            break;
+        }
+
+        let mut parameters = vec![];
+        let mut bits = 0;
+        let mut start = 0;
+        for partition in 0..1 << order {
+            // The warm-up samples come out of the first partition
+            let len = if partition == 0 { partition_len - predictor_order } else { partition_len };
+            let (parameter, partition_bits) = rice_parameter(&residual[start..start + len]);
+            parameters.push(parameter);
+            bits += partition_bits;
+            start += len;
+        }
+        let parameter_len = if parameters.iter().any(|p| *p > MAX_RICE4_PARAMETER) { 5 } else { 4 };
+        bits += 2 + 4 + (parameters.len() as u64) * parameter_len;
+
+        if best.as_ref().map_or(true, |b| bits < b.bits) {
+            best = Some(RicePartitions { order, parameters, bits });
+        }
+    }
+    best
+}
+
+// The Rice parameter that codes `residual` in the fewest bits, and how
+// many bits that is
+fn rice_parameter(residual: &[i64]) -> (u32, u64) {
+    let cost = |parameter: u32| -> u64 {
+        residual
+            .iter()
+            .map(|r| (zigzag(*r) >> parameter) + 1 + u64::from(parameter))
+            .sum()
+    };
+    let sum: u64 = residual.iter().map(|r| zigzag(*r)).sum();
+    let mean = sum / (residual.len() as u64).max(1);
+    // The best parameter is close to log2 of the mean, so only look
+    // either side of it
+    let guess = (64 - mean.leading_zeros()).min(MAX_RICE5_PARAMETER);
+    (guess.saturating_sub(1)..=(guess + 1).min(MAX_RICE5_PARAMETER))
+        .map(|p| (p, cost(p)))
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+fn write_residual(w: &mut BitWriter, residual: &[i64], predictor_order: usize, partitions: &RicePartitions) {
+    let wide = partitions.parameters.iter().any(|p| *p > MAX_RICE4_PARAMETER);
+    let partition_len = (residual.len() + predictor_order) >> partitions.order;
+    /* method */        w.write(if wide { 1 } else { 0 }, 2);
+    /* order */         w.write(u64::from(partitions.order), 4);
+
+    let mut start = 0;
+    for (partition, parameter) in partitions.parameters.iter().enumerate() {
+        let len = if partition == 0 { partition_len - predictor_order } else { partition_len };
+        /* parameter */ w.write(u64::from(*parameter), if wide { 5 } else { 4 });
+        for r in &residual[start..start + len] {
+            let value = zigzag(*r);
+            w.write_unary(value >> parameter);
+            w.write(value, *parameter);
+        }
+        start += len;
+    }
+}
+
+// Fold negative numbers in between the positive ones: 0, -1, 1, -2, 2...
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+// CRC-8 with polynomial x^8 + x^2 + x + 1, for frame headers
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// CRC-16 with polynomial x^16 + x^15 + x^2 + 1, for whole frames
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// Packs values into bytes, most significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: vec![],
+            pending: 0,
+            pending_bits: 0,
+        }
+    }
+
+    // Write the low `bits` bits of `value`, at most 32 at a time
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xffff_ffff, 32);
+            return;
+        }
+        self.pending = (self.pending << bits) | (value & ((1 << bits) - 1));
+        self.pending_bits += bits;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes.push((self.pending >> self.pending_bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    // `zeros` zero bits followed by a one
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    // Frame numbers use the same variable-length coding as UTF-8,
+    // extended to 36 bits
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let mut len = 2;
+        while len < 7 && value >= 1 << (5 * len + 1) {
+            len += 1;
+        }
+        let lead = (0xff00u64 >> len) & 0xff;
+        self.write(lead | (value >> (6 * (len - 1))), 8);
+        for i in (0..len - 1).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3f), 8);
+        }
+    }
+
+    // Pad with zeros to the next byte boundary
+    fn align(&mut self) {
+        if self.pending_bits > 0 {
+            let bits = 8 - self.pending_bits;
+            self.write(0, bits);
+        }
+    }
+
+    // Everything written so far that fills a whole byte
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wav::ChannelLayout;
+
+    // Reads back what `BitWriter` packs
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn new(bytes: &'a [u8]) -> BitReader<'a> {
+            BitReader { bytes, pos: 0 }
+        }
+
+        fn read(&mut self, bits: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..bits {
+                let bit = (self.bytes[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+                value = (value << 1) | u64::from(bit);
+                self.pos += 1;
+            }
+            value
+        }
+
+        fn read_signed(&mut self, bits: u32) -> i64 {
+            let value = self.read(bits);
+            ((value << (64 - bits)) as i64) >> (64 - bits)
+        }
+
+        fn read_unary(&mut self) -> u64 {
+            let mut zeros = 0;
+            while self.read(1) == 0 {
+                zeros += 1;
+            }
+            zeros
+        }
+
+        fn read_utf8(&mut self) -> u64 {
+            let lead = self.read(8);
+            let len = (lead as u8).leading_ones();
+            if len == 0 {
+                return lead;
+            }
+            let mut value = lead & (0x7f >> len);
+            for _ in 1..len {
+                value = (value << 6) | (self.read(8) & 0x3f);
+            }
+            value
+        }
+
+        fn align(&mut self) {
+            self.pos = (self.pos + 7) / 8 * 8;
+        }
+    }
+
+    fn unzigzag(value: u64) -> i64 {
+        (value >> 1) as i64 ^ -((value & 1) as i64)
+    }
+
+    fn read_residual(r: &mut BitReader, block_len: usize, predictor_order: usize) -> Vec<i64> {
+        let wide = r.read(2) == 1;
+        let order = r.read(4) as u32;
+        let partition_len = block_len >> order;
+        let mut residual = vec![];
+        for partition in 0..1 << order {
+            let parameter = r.read(if wide { 5 } else { 4 }) as u32;
+            let len = if partition == 0 { partition_len - predictor_order } else { partition_len };
+            for _ in 0..len {
+                let value = (r.read_unary() << parameter) | r.read(parameter);
+                residual.push(unzigzag(value));
+            }
+        }
+        residual
+    }
+
+    fn read_subframe(r: &mut BitReader, block_len: usize, bits_per_sample: u32) -> Vec<i64> {
+        let header = r.read(8);
+        assert_eq!(header & 0x81, 0, "padding and wasted bits must be clear");
+        match header >> 1 {
+            0 => vec![r.read_signed(bits_per_sample); block_len],
+            1 => (0..block_len).map(|_| r.read_signed(bits_per_sample)).collect(),
+            t if t & 0x38 == 0x08 => {
+                let order = (t & 7) as usize;
+                let mut samples: Vec<i64> = (0..order).map(|_| r.read_signed(bits_per_sample)).collect();
+                for e in read_residual(r, block_len, order) {
+                    let n = samples.len();
+                    let s = |i: usize| samples[n - i];
+                    let prediction = match order {
+                        0 => 0,
+                        1 => s(1),
+                        2 => 2 * s(1) - s(2),
+                        3 => 3 * s(1) - 3 * s(2) + s(3),
+                        _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+                    };
+                    samples.push(prediction + e);
+                }
+                samples
+            }
+            t => panic!("unexpected subframe type {}", t),
+        }
+    }
+
+    // Decode the kinds of stream `write_flac_to` produces, checking every
+    // CRC on the way, and return the samples and the STREAMINFO MD5
+    fn decode(file: &[u8]) -> (Vec<Vec<i64>>, Vec<u8>) {
+        assert_eq!(&file[..4], b"fLaC");
+        let mut pos = 4;
+        let mut streaminfo = None;
+        loop {
+            let header = file[pos];
+            let len = (usize::from(file[pos + 1]) << 16) | (usize::from(file[pos + 2]) << 8) | usize::from(file[pos + 3]);
+            if header & 0x7f == BLOCK_STREAMINFO {
+                streaminfo = Some(&file[pos + 4..pos + 4 + len]);
+            }
+            pos += 4 + len;
+            if header & 0x80 != 0 {
+                break;
+            }
+        }
+        let streaminfo = streaminfo.unwrap();
+        let mut info = BitReader::new(streaminfo);
+        let min_block_size = info.read(16) as usize;
+        let max_block_size = info.read(16) as usize;
+        assert!(min_block_size >= MIN_BLOCK_SIZE && min_block_size <= max_block_size);
+        info.read(24 + 24 + 20);
+        let num_channels = info.read(3) as usize + 1;
+        let bits_per_sample = info.read(5) as u32 + 1;
+        let total = info.read(36) as usize;
+
+        let mut channels = vec![vec![]; num_channels];
+        let mut expected_frame = 0;
+        let mut r = BitReader::new(&file[pos..]);
+        while r.pos / 8 < r.bytes.len() {
+            let start = r.pos / 8;
+            assert_eq!(r.read(16), 0xfff8);
+            let size_code = r.read(4);
+            r.read(4);
+            let assignment = r.read(4);
+            r.read(4);
+            assert_eq!(r.read_utf8(), expected_frame);
+            expected_frame += 1;
+            let block_len = match size_code {
+                12 => BLOCK_SIZE,
+                7 => r.read(16) as usize + 1,
+                c => panic!("unexpected block size code {}", c),
+            };
+            let crc = crc8(&r.bytes[start..r.pos / 8]);
+            assert_eq!(r.read(8), u64::from(crc));
+
+            if assignment == CHANNELS_LEFT_SIDE {
+                let left = read_subframe(&mut r, block_len, bits_per_sample);
+                let side = read_subframe(&mut r, block_len, bits_per_sample + 1);
+                channels[1].extend(left.iter().zip(&side).map(|(l, s)| l - s));
+                channels[0].extend(left);
+            } else {
+                assert_eq!(assignment as usize + 1, num_channels);
+                for channel in channels.iter_mut() {
+                    channel.extend(read_subframe(&mut r, block_len, bits_per_sample));
+                }
+            }
+            r.align();
+            let crc = crc16(&r.bytes[start..r.pos / 8]);
+            assert_eq!(r.read(16), u64::from(crc));
+        }
+        assert!(channels.iter().all(|c| c.len() == total));
+        (channels, streaminfo[18..34].to_vec())
+    }
+
+    fn round_trip(samples: &[f64], options: &WavOptions) {
+        let mut file = vec![];
+        write_flac_to(44100, samples, options, &mut file).unwrap();
+        let (channels, signature) = decode(&file);
+
+        let layout = options.layout;
+        let mut expected = vec![vec![]; usize::from(layout.channels())];
+        let mut interleaved = vec![];
+        let bytes_per_sample = options.format.bytes_per_sample() as usize;
+        for sample in samples {
+            for (channel, output) in expected.iter_mut().enumerate() {
+                let value = options.format.quantize(layout.channel_value(*sample, channel)).unwrap();
+                output.push(i64::from(value));
+                interleaved.extend_from_slice(&value.to_le_bytes()[..bytes_per_sample]);
+            }
+        }
+        assert_eq!(channels, expected);
+        assert_eq!(signature, md5::digest(&interleaved));
+    }
+
+    // A tone that doesn't fit a whole number of times in a block, then
+    // silence, then noise that no predictor helps with
+    fn test_signal() -> Vec<f64> {
+        let mut seed = 1u32;
+        let mut samples: Vec<f64> = (0..5000).map(|i| (f64::from(i) * 0.37).sin() * 0.8).collect();
+        samples.extend(vec![0.0; 4096]);
+        samples.extend((0..3000).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            f64::from(seed >> 16) / 32768.0 - 1.0
+        }));
+        samples
+    }
+
+    #[test]
+    fn round_trip_mono() {
+        let samples = test_signal();
+        for &format in &[SampleFormat::U8, SampleFormat::S16, SampleFormat::S24] {
+            round_trip(&samples, &WavOptions { format, ..WavOptions::default() });
+        }
+    }
+
+    #[test]
+    fn round_trip_stereo() {
+        let samples = test_signal();
+        for &layout in &[ChannelLayout::Both, ChannelLayout::Left, ChannelLayout::Differential] {
+            round_trip(&samples, &WavOptions { layout, ..WavOptions::default() });
+        }
+    }
+
+    #[test]
+    fn short_and_empty() {
+        round_trip(&[0.5], &WavOptions::default());
+        round_trip(&[], &WavOptions::default());
+    }
+
+    #[test]
+    fn block_sizes() {
+        let block_sizes = |len: usize| {
+            let mut file = vec![];
+            write_flac_to(44100, &vec![0.25; len], &WavOptions::default(), &mut file).unwrap();
+            // STREAMINFO follows the marker and its block header
+            let size = |at: usize| usize::from(file[at]) << 8 | usize::from(file[at + 1]);
+            (size(8), size(10))
+        };
+        assert_eq!(block_sizes(0), (16, 16));
+        assert_eq!(block_sizes(15), (16, 16));
+        assert_eq!(block_sizes(16), (16, 16));
+        assert_eq!(block_sizes(1000), (1000, 1000));
+        assert_eq!(block_sizes(5000), (BLOCK_SIZE, BLOCK_SIZE));
+        round_trip(&[0.25; 15], &WavOptions::default());
+    }
+
+    #[test]
+    fn bit_writer_round_trip() {
+        let mut w = BitWriter::new();
+        w.write(0b101, 3);
+        w.write(0x1_2345_6789, 36);
+        w.write_signed(-5, 7);
+        w.write_unary(0);
+        w.write_unary(40);
+        w.write(0xffff_ffff, 32);
+        w.write_utf8(1_000_000);
+        let bytes = w.into_bytes();
+
+        let mut r = BitReader::new(&bytes);
+        assert_eq!(r.read(3), 0b101);
+        assert_eq!(r.read(36), 0x1_2345_6789);
+        assert_eq!(r.read_signed(7), -5);
+        assert_eq!(r.read_unary(), 0);
+        assert_eq!(r.read_unary(), 40);
+        assert_eq!(r.read(32), 0xffff_ffff);
+        assert_eq!(r.read_utf8(), 1_000_000);
+        r.align();
+        assert_eq!(r.pos, bytes.len() * 8);
+    }
+
+    // Frame numbers in the Unicode range code exactly as UTF-8 does
+    #[test]
+    fn utf8_frame_numbers() {
+        for &value in &[0u32, 0x7f, 0x80, 0x7ff, 0x800, 0xffff, 0x1_0000, 0x10_ffff] {
+            let mut w = BitWriter::new();
+            w.write_utf8(u64::from(value));
+            let mut expected = [0; 4];
+            let expected = std::char::from_u32(value).unwrap().encode_utf8(&mut expected);
+            assert_eq!(w.into_bytes(), expected.as_bytes());
+        }
+        let mut w = BitWriter::new();
+        w.write_utf8((1 << 36) - 1);
+        assert_eq!(w.into_bytes(), [0xfe, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf]);
+    }
+
+    #[test]
+    fn rice_round_trip() {
+        let residual: Vec<i64> = (0..256)
+            .map(|i: i64| if i % 50 == 0 { -40_000 * i } else { (i * 7919) % 61 - 30 })
+            .collect();
+        for predictor_order in 0..=MAX_FIXED_ORDER {
+            let partitions = choose_partitions(&residual[predictor_order..], 256, predictor_order).unwrap();
+            let mut w = BitWriter::new();
+            write_residual(&mut w, &residual[predictor_order..], predictor_order, &partitions);
+            let bytes = w.into_bytes();
+            assert_eq!(
+                read_residual(&mut BitReader::new(&bytes), 256, predictor_order),
+                &residual[predictor_order..]
+            );
+        }
+    }
+
+    // The CRC catalogue's check values: CRC-8/SMBUS and CRC-16/UMTS
+    #[test]
+    fn crcs() {
+        assert_eq!(crc8(b"123456789"), 0xf4);
+        assert_eq!(crc16(b"123456789"), 0xfee8);
+    }
+}
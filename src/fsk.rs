@@ -1,15 +1,21 @@
 use std;
 use std::f64;
 
+pub use ltc_core::fsk::Framing;
+use ltc_core::fsk::baud_increment;
+use ltc_core::packet::MODE_HEADER_LEN;
+
 pub struct FskEncoder {
-    baud_frac: f64,
-    baud_incr: f64,
+    baud_frac: u32,
+    baud_incr: u32,
     phase: f64,
-    omega_lo: f64,
-    omega_hi: f64,
+    omegas: Vec<f64>,
+    bits_per_symbol: u32,
+    framing: Framing,
+    shaping: Option<Vec<f64>>,
 
-    current_bit: u8,
-    current_byte: u8,
+    current_symbol: usize,
+    current_word: u32,
     bit_pos: u32,
     data_pos: usize,
 
@@ -19,61 +25,302 @@ pub struct FskEncoder {
 
 impl FskEncoder {
 
-    pub fn new(f_lo: f64, f_hi: f64, baud_rate: f64, sample_rate: f64) -> FskEncoder {
+    /// Create a new M-FSK encoder.  There must be a power-of-two number of
+    /// `tones`, and each symbol carries log2(tones.len()) bits, with the
+    /// first bit sent as the least significant bit of the tone index.
+    /// Binary FSK is simply `[f_lo, f_hi]`.  The first `MODE_HEADER_LEN`
+    /// bytes of each burst are sent one bit per symbol, on the first two
+    /// tones.
+    ///
+    /// If `gaussian_bt` is set, the instantaneous frequency is smoothed by a
+    /// Gaussian filter with that bandwidth-time product (GFSK) rather than
+    /// switching tones at each symbol boundary.
+    pub fn new(
+        tones: &[f64],
+        baud_rate: f64,
+        sample_rate: f64,
+        framing: Framing,
+        gaussian_bt: Option<f64>,
+    ) -> FskEncoder {
+        assert!(
+            tones.len() >= 2 && tones.len().is_power_of_two(),
+            "FSK needs a power-of-two number of tones"
+        );
         FskEncoder {
             sample_rate,
             baud_rate,
 
             phase: 0.0,
-            omega_lo: (2.0 * std::f64::consts::PI * f_lo) / sample_rate,
-            omega_hi: (2.0 * std::f64::consts::PI * f_hi) / sample_rate,
-            baud_frac: 0.0,
-            baud_incr: baud_rate / sample_rate,
+            omegas: tones
+                .iter()
+                .map(|f| (2.0 * std::f64::consts::PI * f) / sample_rate)
+                .collect(),
+            bits_per_symbol: tones.len().trailing_zeros(),
+            baud_frac: 0,
+            baud_incr: baud_increment(baud_rate, sample_rate),
+            framing,
+            shaping: gaussian_bt.map(|bt| gaussian_taps(bt, sample_rate / baud_rate)),
 
-            current_bit: 0,
-            current_byte: 0,
+            current_symbol: 0,
+            current_word: 0,
             bit_pos: 0,
             data_pos: 0,
         }
     }
 
+    /// Number of bits carried by each symbol
+    pub fn bits_per_symbol(&self) -> u32 {
+        self.bits_per_symbol
+    }
+
     // does what you think it does -- input data should be uint8 array, outputdata is floats
     pub fn modulate(&mut self, input: &[u8]) -> Vec<f64> {
+        let omegas = self.instantaneous_omegas(input);
+        let omegas = match self.shaping {
+            Some(ref taps) => smooth(&omegas, taps),
+            None => omegas,
+        };
+
+        let mut output: Vec<f64> = Vec::with_capacity(omegas.len());
+        for omega in omegas {
+            output.push(self.phase.cos());
+            self.phase += omega;
+        }
+        output
+    }
+
+    /// A steady tone, phase-continuous with the bursts either side, to pad
+    /// around a burst.  Before a burst this is the lowest tone, which the
+    /// preamble starts on, and after one it holds the last symbol's tone.
+    pub fn carrier(&mut self, samples: usize, lead_in: bool) -> Vec<f64> {
+        let omega = self.omegas[if lead_in { 0 } else { self.current_symbol }];
+        let mut output: Vec<f64> = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            output.push(self.phase.cos());
+            self.phase += omega;
+        }
+        output
+    }
+
+    // Work out the phase increment for every output sample
+    fn instantaneous_omegas(&mut self, input: &[u8]) -> Vec<f64> {
         let mut output: Vec<f64> = Vec::new();
         self.data_pos = 0;
 
         /* We keep these values the same between runs */
         /*
         self.bit_pos = 0;
-        self.current_byte = 0;
+        self.current_word = 0;
         self.baud_frac = 0.0;
         */
-        output.reserve(8 * input.len() as usize * self.sample_rate as usize / self.baud_rate as usize);
+        let bits_per_byte = self.framing.bits_per_byte() as usize;
+        output.reserve(
+            bits_per_byte * input.len() * self.sample_rate as usize
+                / self.baud_rate as usize
+                / self.bits_per_symbol as usize,
+        );
 
+        // Bit timing uses the same fixed-point accumulator as
+        // `FskGenerator`, so that both start every bit on the same sample
+        // and don't drift however long the burst.
         loop {
-            self.baud_frac += self.baud_incr;
-            if self.baud_frac >= 1.0 {
-                self.baud_frac -= 1.0;
-                assert!(self.baud_frac < 1.0);
-                if self.bit_pos == 0 {
-                    if self.data_pos < input.len() {
-                        self.current_byte = input[self.data_pos];
-                        self.data_pos += 1;
-                        self.bit_pos = 8;
-                    } else {
-                        return output;
+            let (baud_frac, new_symbol) = self.baud_frac.overflowing_add(self.baud_incr);
+            self.baud_frac = baud_frac;
+            if new_symbol {
+                let in_header = self.in_mode_header();
+                let mut symbol = match self.next_bit(input) {
+                    Some(bit) => bit,
+                    None => return output,
+                };
+                // The header is binary FSK on the first two tones, just
+                // as a two-tone encoder sends it.  After that, if the data
+                // runs out partway through a symbol, pad it out with mark
+                // bits as an idle line would.
+                if !in_header {
+                    for i in 1..self.bits_per_symbol {
+                        symbol |= self.next_bit(input).unwrap_or(1) << i;
                     }
                 }
-                self.current_bit = self.current_byte & 1;
-                self.current_byte >>= 1;
-                self.bit_pos -= 1;
+                self.current_symbol = symbol as usize;
             }
-            output.push(self.phase.cos());
-            if self.current_bit == 0 {
-                self.phase += self.omega_lo;
+            output.push(self.omegas[self.current_symbol]);
+        }
+    }
+
+    // Whether the next bit belongs to the part of the burst that is sent
+    // one bit per symbol
+    fn in_mode_header(&self) -> bool {
+        let byte = if self.bit_pos > 0 { self.data_pos - 1 } else { self.data_pos };
+        byte < MODE_HEADER_LEN
+    }
+
+    // Pull the next framed bit out of the input, LSB first
+    fn next_bit(&mut self, input: &[u8]) -> Option<u32> {
+        if self.bit_pos == 0 {
+            if self.data_pos < input.len() {
+                self.current_word = self.framing.frame(input[self.data_pos]);
+                self.data_pos += 1;
+                self.bit_pos = self.framing.bits_per_byte();
             } else {
-                self.phase += self.omega_hi;
+                return None;
             }
         }
+        let bit = self.current_word & 1;
+        self.current_word >>= 1;
+        self.bit_pos -= 1;
+        Some(bit)
+    }
+}
+
+// Build a normalised Gaussian pulse-shaping filter, truncated at three
+// standard deviations either side of the centre tap.
+fn gaussian_taps(bt: f64, samples_per_bit: f64) -> Vec<f64> {
+    let sigma = (2.0f64.ln()).sqrt() / (2.0 * f64::consts::PI * bt) * samples_per_bit;
+    let half = (3.0 * sigma).ceil() as isize;
+    let mut taps: Vec<f64> = (-half..=half)
+        .map(|n| (-(n * n) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap /= sum;
+    }
+    taps
+}
+
+// Convolve with the filter, holding the first and last values
+// so the burst starts and ends on a steady tone.
+fn smooth(input: &[f64], taps: &[f64]) -> Vec<f64> {
+    if input.is_empty() {
+        return vec![];
+    }
+    let half = taps.len() / 2;
+    let first = input[0];
+    let last = input[input.len() - 1];
+    let at = |i: isize| {
+        if i < 0 {
+            first
+        } else if i as usize >= input.len() {
+            last
+        } else {
+            input[i as usize]
+        }
+    };
+
+    (0..input.len())
+        .map(|i| {
+            taps.iter()
+                .enumerate()
+                .map(|(k, tap)| tap * at(i as isize + k as isize - half as isize))
+                .sum()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ltc_core::packet::{self, PacketBuilder, ProtocolVersion};
+    use modulator::Modulate;
+
+    // Non-coherent receiver: correlate each symbol against every tone, read
+    // the header as binary FSK on the first two tones, then unframe.
+    fn demodulate(tones: &[f64], baud_rate: f64, sample_rate: f64, framing: Framing, samples: &[f64]) -> Vec<u8> {
+        let mut starts = vec![];
+        let baud_incr = baud_increment(baud_rate, sample_rate);
+        let mut baud_frac = 0u32;
+        for i in 0..samples.len() {
+            let (frac, new_symbol) = baud_frac.overflowing_add(baud_incr);
+            baud_frac = frac;
+            if new_symbol {
+                starts.push(i);
+            }
+        }
+        starts.push(samples.len());
+
+        let bits_per_byte = framing.bits_per_byte() as usize;
+        let bits_per_symbol = tones.len().trailing_zeros() as usize;
+        let mut bits = vec![];
+        for window in starts.windows(2) {
+            let symbol = &samples[window[0]..window[1]];
+            let energy = |f: &f64| {
+                let omega = 2.0 * f64::consts::PI * f / sample_rate;
+                let (re, im) = symbol.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, s)| {
+                    (re + s * (omega * n as f64).cos(), im + s * (omega * n as f64).sin())
+                });
+                re * re + im * im
+            };
+            let index = (0..tones.len())
+                .max_by(|a, b| energy(&tones[*a]).partial_cmp(&energy(&tones[*b])).unwrap())
+                .unwrap();
+            if bits.len() < MODE_HEADER_LEN * bits_per_byte {
+                assert!(index < 2, "header symbol on tone {}", index);
+                bits.push(index as u32);
+            } else {
+                bits.extend((0..bits_per_symbol).map(|i| (index >> i) as u32 & 1));
+            }
+        }
+
+        bits.chunks(bits_per_byte)
+            .filter(|word| word.len() == bits_per_byte)
+            .map(|word| {
+                let word = word.iter().rev().fold(0, |acc, bit| (acc << 1) | bit);
+                (0..=255u8)
+                    .find(|byte| framing.frame(*byte) == word)
+                    .expect("invalid framing")
+            })
+            .collect()
+    }
+
+    fn loopback(tones: &[f64], baud_rate: f64, sample_rate: f64, framing: Framing, gaussian_bt: Option<f64>) {
+        let input: Vec<u8> = (0..=255).collect();
+        let mut encoder = FskEncoder::new(tones, baud_rate, sample_rate, framing, gaussian_bt);
+        let samples = encoder.modulate(&input);
+        assert_eq!(demodulate(tones, baud_rate, sample_rate, framing, &samples), input);
+    }
+
+    #[test]
+    fn binary_loopback() {
+        loopback(&[8000.0, 12000.0], 1000.0, 48000.0, Framing::Raw, None);
+        loopback(&[8666.0, 12500.0], 1200.0, 44100.0, Framing::Raw, None);
+    }
+
+    #[test]
+    fn mfsk_loopback() {
+        loopback(&[6000.0, 7000.0, 8000.0, 9000.0], 1000.0, 48000.0, Framing::Raw, None);
+        let tones: Vec<f64> = (0..8).map(|i| 4000.0 + 1200.0 * f64::from(i)).collect();
+        loopback(&tones, 1200.0, 44100.0, Framing::Raw, None);
+    }
+
+    #[test]
+    fn async_framing_loopback() {
+        loopback(&[8000.0, 12000.0], 1000.0, 48000.0, Framing::Async { stop_bits: 1 }, None);
+        loopback(&[6000.0, 7000.0, 8000.0, 9000.0], 1000.0, 48000.0, Framing::Async { stop_bits: 2 }, None);
+    }
+
+    #[test]
+    fn gfsk_loopback() {
+        loopback(&[8000.0, 12000.0], 1000.0, 48000.0, Framing::Raw, Some(0.5));
+    }
+
+    // The mode header goes out one bit per symbol on the first two tones,
+    // whatever the tone count, and the rest of the version byte says how
+    // many bits each symbol carries from then on
+    #[test]
+    fn header_is_binary() {
+        let tones = [6000.0, 7000.0, 8000.0, 9000.0, 10000.0, 11000.0, 12000.0, 13000.0];
+        let header: Vec<u8> = (0..MODE_HEADER_LEN).map(|i| (i * 37 + 5) as u8).collect();
+        let mut binary = FskEncoder::new(&tones[..2], 1000.0, 48000.0, Framing::Raw, None);
+        let expected = binary.modulate(&header);
+        for &(count, mode) in &[(2, 0), (4, 1), (8, 2)] {
+            let mut encoder = FskEncoder::new(&tones[..count], 1000.0, 48000.0, Framing::Raw, None);
+            assert_eq!(encoder.modulate(&header), expected);
+
+            let mut packet = [0; packet::CONTROL_PACKET_LEN];
+            let builder = PacketBuilder::new(ProtocolVersion::V2, Modulate::mode(&encoder), false);
+            builder.control_packet(&[0; 16], &mut packet);
+            let version = packet[MODE_HEADER_LEN - 1];
+            assert_eq!(version >> 4, mode);
+            assert_eq!(version & 0xf, ProtocolVersion::V2.as_num());
+        }
     }
 }
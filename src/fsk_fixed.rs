@@ -0,0 +1,81 @@
+use fsk;
+use ltc_core::fsk::{baud_increment, phase_increment, FskGenerator};
+
+/// FSK encoder that needs no floating point once it has been set up,
+/// built on the same `FskGenerator` that runs on small microcontrollers.
+///
+/// Compared with `fsk::FskEncoder` scaled to 32767, each sample is within
+/// 4 LSB, plus a slowly growing phase error of at most 2π·n/2^33 radians
+/// after n samples from rounding the phase increments.  Both encoders use
+/// the same fixed-point bit timing, so they always start each bit on the
+/// same sample.
+///
+/// Gaussian shaping is not supported.
+pub struct FixedFskEncoder {
+    generator: FskGenerator<Vec<u32>>,
+}
+
+impl FixedFskEncoder {
+    /// Work out the accumulator increments from frequencies.  This is the
+    /// only place floating point is used.
+    pub fn new(tones: &[f64], baud_rate: f64, sample_rate: f64, framing: fsk::Framing) -> FixedFskEncoder {
+        let phase_incrs = tones
+            .iter()
+            .map(|f| phase_increment(*f, sample_rate))
+            .collect();
+        FixedFskEncoder {
+            generator: FskGenerator::new(
+                phase_incrs,
+                baud_increment(baud_rate, sample_rate),
+                framing,
+            ),
+        }
+    }
+
+    pub fn bits_per_symbol(&self) -> u32 {
+        self.generator.bits_per_symbol()
+    }
+
+    pub fn modulate(&mut self, input: &[u8]) -> Vec<i16> {
+        self.generator.generate_vec(input)
+    }
+
+    /// A steady tone to pad around a burst, as `fsk::FskEncoder::carrier`
+    pub fn carrier(&mut self, samples: usize, lead_in: bool) -> Vec<i16> {
+        let mut output = vec![0; samples];
+        self.generator.carrier(lead_in, &mut output);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64;
+
+    fn compare(tones: &[f64], baud_rate: f64, sample_rate: f64, framing: fsk::Framing) {
+        let input: Vec<u8> = (0..=255).collect();
+        let float = fsk::FskEncoder::new(tones, baud_rate, sample_rate, framing, None).modulate(&input);
+        let fixed = FixedFskEncoder::new(tones, baud_rate, sample_rate, framing).modulate(&input);
+        assert_eq!(fixed.len(), float.len());
+        for (n, (fixed, float)) in fixed.iter().zip(&float).enumerate() {
+            let phase_error = 2.0 * f64::consts::PI * (n + 1) as f64 / 2f64.powi(33);
+            let bound = 4.0 + 32767.0 * phase_error;
+            let error = (f64::from(*fixed) - float * 32767.0).abs();
+            assert!(error <= bound, "sample {} is {} out, more than {}", n, error, bound);
+        }
+    }
+
+    #[test]
+    fn matches_float_44100() {
+        compare(&[8666.0, 12500.0], 8000.0, 44100.0, fsk::Framing::Raw);
+        compare(&[1200.0, 2200.0], 1200.0, 44100.0, fsk::Framing::Async { stop_bits: 1 });
+    }
+
+    // 8000 baud at 48 kHz puts every bit boundary exactly on a sample
+    #[test]
+    fn matches_float_48000() {
+        compare(&[8666.0, 12500.0], 8000.0, 48000.0, fsk::Framing::Raw);
+        compare(&[6000.0, 8000.0, 10000.0, 12000.0], 2000.0, 48000.0, fsk::Framing::Raw);
+    }
+}
@@ -0,0 +1,86 @@
+//! Modulate programs for uploading to a Chibitronics Love-to-Code sticker.
+//!
+//! The `ltc-modulate` command-line tool is built on this library, and the
+//! `wasm` and `ffi` features expose the same encoder to JavaScript and C.
+
+pub mod aiff;
+pub mod au;
+pub mod controller;
+pub mod encoder;
+pub mod eq;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flac;
+pub mod fsk;
+pub mod fsk_fixed;
+pub mod modulator;
+pub mod ofdm;
+pub mod preset;
+pub mod psk;
+pub mod resample;
+pub mod validate;
+pub mod wav;
+// wasm-bindgen itself needs Rust 1.81, so the MSRV in clippy.toml can't
+// apply to this module, and the code its macros generate trips the lint
+#[cfg(feature = "wasm")]
+#[allow(clippy::incompatible_msrv)]
+pub mod wasm;
+
+extern crate ltc_core;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+// const DEFAULT_SAMPLE_RATE: f64 = 48000.0;
+/// Sample rate used when none is given
+pub const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+
+/// How many times slower than normal the program is sent.  Data, silence
+/// and pilot are all stretched by the same factor: tones and baud rate
+/// are divided by it and every gap is multiplied by it, while the output
+/// sample rate stays the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodingRate {
+    stretch: u32,
+}
+
+impl EncodingRate {
+    pub const HIGH: EncodingRate = EncodingRate { stretch: 1 };
+    pub const MID: EncodingRate = EncodingRate { stretch: 2 };
+    pub const LOW: EncodingRate = EncodingRate { stretch: 4 };
+
+    /// Accepts "high", "mid", "low", or a whole-number stretch factor
+    pub fn parse(s: &str) -> Option<EncodingRate> {
+        match s {
+            "high" => Some(EncodingRate::HIGH),
+            "mid" => Some(EncodingRate::MID),
+            "low" => Some(EncodingRate::LOW),
+            x => match x.parse::<u32>() {
+                Ok(stretch) if stretch > 0 => Some(EncodingRate { stretch }),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn stretch(self) -> u32 {
+        self.stretch
+    }
+
+    // Running the modulator this much faster than the output, and then
+    // playing the result at the output rate, divides every frequency in
+    // it by the stretch factor.
+    pub fn modulation_rate(self, sample_rate: f64) -> f64 {
+        sample_rate * f64::from(self.stretch)
+    }
+}
+
+impl core::fmt::Display for EncodingRate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            EncodingRate::HIGH => write!(f, "High"),
+            EncodingRate::MID => write!(f, "Mid"),
+            EncodingRate::LOW => write!(f, "Low"),
+            EncodingRate { stretch } => write!(f, "{}x stretch", stretch),
+        }
+    }
+}
+
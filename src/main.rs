@@ -1,49 +1,42 @@
-mod controller;
-mod fsk;
-mod modulator;
-mod wav;
 extern crate cpal;
 extern crate elf;
+extern crate ltc_core;
+extern crate ltc_modulate;
+use ltc_modulate::{aiff, au, controller, eq, flac, fsk, fsk_fixed, modulator, ofdm, preset, psk, resample, validate, wav};
+use ltc_modulate::{EncodingRate, DEFAULT_SAMPLE_RATE};
 
 extern crate clap;
 use clap::{App, Arg};
 
 use std::fs::File;
 use std::io::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-// const DEFAULT_SAMPLE_RATE: f64 = 48000.0;
-const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+// Output filename meaning standard output
+const STDOUT_NAME: &str = "-";
 
-#[derive(PartialEq)]
-pub enum EncodingRate {
-    Low,
-    Mid,
-    High,
-}
+// Pieces of audio modulated ahead of what is playing
+const PLAYBACK_QUEUE: usize = 4;
 
-impl EncodingRate {
-    pub fn silence_divisor(&self) -> u32 {
-        match *self {
-            EncodingRate::Low => 4,
-            EncodingRate::Mid => 2,
-            EncodingRate::High => 1,
+// Set when the audio itself goes to stdout, so that progress messages
+// don't end up mixed in with it
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
         }
-    }
+    };
 }
 
 enum ModulationError {
     Io(std::io::Error),
     FloatParse(std::num::ParseFloatError),
-}
-
-impl core::fmt::Display for EncodingRate {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match *self {
-            EncodingRate::Low => write!(f, "Low"),
-            EncodingRate::Mid => write!(f, "Mid"),
-            EncodingRate::High => write!(f, "High"),
-        }
-    }
+    IntParse(std::num::ParseIntError),
+    Invalid(validate::ValidationError),
 }
 
 impl std::convert::From<std::io::Error> for ModulationError {
@@ -58,11 +51,25 @@ impl std::convert::From<std::num::ParseFloatError> for ModulationError {
     }
 }
 
+impl std::convert::From<std::num::ParseIntError> for ModulationError {
+    fn from(error: std::num::ParseIntError) -> Self {
+        ModulationError::IntParse(error)
+    }
+}
+
+impl std::convert::From<validate::ValidationError> for ModulationError {
+    fn from(error: validate::ValidationError) -> Self {
+        ModulationError::Invalid(error)
+    }
+}
+
 impl core::fmt::Debug for ModulationError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match &self {
             ModulationError::Io(e) => write!(f, "Io Error {:?}", e),
             ModulationError::FloatParse(e) => write!(f, "Unable to parse float: {:?}", e),
+            ModulationError::IntParse(e) => write!(f, "Unable to parse integer: {:?}", e),
+            ModulationError::Invalid(e) => write!(f, "Invalid settings: {}", e),
         }
     }
 }
@@ -74,33 +81,174 @@ struct ModulationConfig {
     repeat_count: u32,
     sample_rate: f64,
     baud_rate: f64,
-    f_lo: f64,
-    f_hi: f64,
+    tones: Vec<f64>,
+    framing: fsk::Framing,
+    gaussian_bt: Option<f64>,
+    fixed_point: bool,
+    psk_mode: Option<psk::PskMode>,
+    carrier: f64,
+    ofdm: Option<OfdmConfig>,
+    envelope: controller::Envelope,
+    eq: Option<String>,
+    wav: wav::WavOptions,
+    cue_points: bool,
+    labels: Option<String>,
+    raw: bool,
+}
+
+struct OfdmConfig {
+    fft_size: usize,
+    subcarriers: usize,
+    cyclic_prefix: usize,
+    pilot_spacing: usize,
+}
+
+impl OfdmConfig {
+    // Lay the subcarriers out around the carrier at the given sample rate
+    fn params(&self, sample_rate: f64, carrier: f64) -> ofdm::OfdmParams {
+        ofdm::OfdmParams::centred(
+            sample_rate,
+            carrier,
+            self.fft_size,
+            self.subcarriers,
+            self.cyclic_prefix,
+            self.pilot_spacing,
+        )
+    }
+}
+
+impl ModulationConfig {
+    // One-line summary of the settings, for the WAV file's comment
+    fn describe(&self) -> String {
+        let scheme = if self.ofdm.is_some() {
+            format!("OFDM centred on {} Hz", self.carrier)
+        } else if let Some(mode) = self.psk_mode {
+            format!("{} baud {} on {} Hz", self.baud_rate, mode, self.carrier)
+        } else {
+            let tones: Vec<String> = self.tones.iter().map(|t| t.to_string()).collect();
+            format!("{} baud, tones {} Hz", self.baud_rate, tones.join(", "))
+        };
+        format!(
+            "Protocol version {}, data rate {}, {}",
+            self.version.as_num(),
+            self.data_rate,
+            scheme
+        )
+    }
+
+    fn make_controller(&self) -> controller::Controller {
+        controller::Controller::new(
+            self.sample_rate,
+            self.os_update,
+            self.version,
+            self.make_modulator(self.data_rate.modulation_rate(self.sample_rate)),
+            self.envelope,
+        )
+    }
+
+    fn make_modulator(&self, sample_rate: f64) -> Box<dyn modulator::Modulate> {
+        if let Some(ref ofdm) = self.ofdm {
+            return Box::new(ofdm::OfdmEncoder::new(ofdm.params(sample_rate, self.carrier)));
+        }
+        if let Some(mode) = self.psk_mode {
+            return Box::new(psk::PskEncoder::new(mode, self.carrier, self.baud_rate, sample_rate));
+        }
+        if self.fixed_point {
+            return Box::new(fsk_fixed::FixedFskEncoder::new(
+                &self.tones,
+                self.baud_rate,
+                sample_rate,
+                self.framing,
+            ));
+        }
+        Box::new(fsk::FskEncoder::new(
+            &self.tones,
+            self.baud_rate,
+            sample_rate,
+            self.framing,
+            self.gaussian_bt,
+        ))
+    }
+}
+
+// The output file's extension, in lower case, which picks its format
+fn extension(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// Write an Audacity label track, with one region per marker
+fn write_labels(filename: &str, markers: &[controller::Marker], sample_rate: f64) -> Result<(), std::io::Error> {
+    let mut file = File::create(filename)?;
+    for marker in markers {
+        writeln!(
+            file,
+            "{:.6}\t{:.6}\t{}",
+            marker.start as f64 / sample_rate,
+            (marker.start + marker.length) as f64 / sample_rate,
+            marker.segment
+        )?;
+    }
+    Ok(())
+}
+
+// Cue points are 32-bit sample offsets, so anything that starts later
+// than that is left out, and lengths are cut short to fit
+fn make_cues(markers: &[controller::Marker]) -> Vec<wav::Cue> {
+    let max = u32::MAX as usize;
+    let cues: Vec<wav::Cue> = markers
+        .iter()
+        .filter(|m| m.start <= max)
+        .map(|m| wav::Cue {
+            position: m.start as u32,
+            length: m.length.min(max) as u32,
+            label: m.segment.to_string(),
+        })
+        .collect();
+    if cues.len() < markers.len() {
+        status!(
+            "Warning: {} cue points start beyond sample {} and have been left out",
+            markers.len() - cues.len(),
+            max
+        );
+    }
+    cues
+}
+
+// Where the modulated audio goes as it is produced.  FLAC, AIFF and AU
+// are written in one go, so their samples are gathered up until the end.
+enum Output {
+    Wav(wav::WavWriter<std::io::BufWriter<File>>),
+    Stream(wav::WavWriter<std::io::Stdout>),
+    Raw(Box<dyn Write>),
+    Gather(Vec<f64>),
+}
+
+impl Output {
+    fn write(&mut self, samples: &[f64], options: &wav::WavOptions) -> Result<(), std::io::Error> {
+        match *self {
+            Output::Wav(ref mut writer) => writer.write_samples(samples),
+            Output::Stream(ref mut writer) => writer.write_samples(samples),
+            Output::Raw(ref mut file) => wav::write_raw_to(samples, options, file),
+            Output::Gather(ref mut audio) => {
+                audio.extend_from_slice(samples);
+                Ok(())
+            }
+        }
+    }
 }
 
 fn do_modulation(
     source_filename: &str,
     target_filename: &str,
     play_file: bool,
-    cfg: ModulationConfig,
+    mut cfg: ModulationConfig,
 ) -> Result<(), std::io::Error> {
-    let sample_rate = match cfg.data_rate {
-        EncodingRate::Low => cfg.sample_rate * 4.0,
-        EncodingRate::Mid => cfg.sample_rate * 2.0,
-        EncodingRate::High => cfg.sample_rate * 1.0,
-    };
-    let mut controller = controller::Controller::new(
-        sample_rate,
-        cfg.os_update,
-        cfg.version,
-        cfg.baud_rate,
-        cfg.f_lo,
-        cfg.f_hi,
-    );
-
     let input_data = match elf::File::open_path(source_filename) {
         Ok(e) => {
-            println!("opened ELF file: {}", e.ehdr);
+            status!("opened ELF file: {}", e.ehdr);
             if e.ehdr.machine != elf::types::EM_ARM {
                 panic!("ELF file detected, but not for ARM");
             }
@@ -131,99 +279,223 @@ fn do_modulation(
             input_data
         }
     };
-    let mut audio_data: Vec<f64> = vec![];
+    let mut equalizer = match cfg.eq {
+        Some(ref eq) => Some(match eq::Equalizer::preset(eq, cfg.sample_rate) {
+            Some(e) => e,
+            None => eq::Equalizer::from_response_file(eq, cfg.sample_rate)?,
+        }),
+        None => None,
+    };
 
-    for _ in 0..cfg.repeat_count {
-        controller.encode(&input_data, &mut audio_data, &cfg.data_rate);
-        let mut pilot_controller = controller::Controller::new(
-            cfg.sample_rate,
-            cfg.os_update,
-            cfg.version,
-            cfg.baud_rate,
-            cfg.f_lo,
-            cfg.f_hi,
-        );
-        pilot_controller.pilot(&mut audio_data, &cfg.data_rate);
+    if play_file {
+        play(input_data, equalizer, cfg);
     }
 
-    if play_file {
-        let endpoint = cpal::default_endpoint().expect("Failed to get default endpoint");
-        let format = endpoint
-            .supported_formats()
-            .unwrap()
-            .next()
-            .expect("Failed to get endpoint format")
-            .with_max_samples_rate();
-        println!("Format selected: {:?}", format);
-
-        let event_loop = cpal::EventLoop::new();
-        let voice_id = event_loop.build_voice(&endpoint, &format).unwrap();
-        event_loop.play(voice_id);
-
-        let audio_data_len = audio_data.len();
-        let mut audio_data_pos = 0;
-        let mut overrun_count = 0;
-
-        // Produce a sinusoid of maximum amplitude.
-        let mut next_value = || {
-            if audio_data_pos >= audio_data_len {
-                overrun_count += 1;
-                // After 250ms of silence, exit the program.
-                if overrun_count > (sample_rate as u32 / 4) {
-                    use std::process;
-                    process::exit(0);
-                }
-                0.0 as f32
-            } else {
-                let val = audio_data[audio_data_pos];
-                audio_data_pos += 1;
-                val as f32
+    let guid: Vec<String> = ltc_core::md5::digest(&input_data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    cfg.wav.info = vec![
+        (*b"INAM", source_filename.to_owned()),
+        (*b"ICMT", format!("{}, program GUID {}", cfg.describe(), guid.concat())),
+        (*b"ISFT", format!("ltc-modulate {}", env!("CARGO_PKG_VERSION"))),
+    ];
+
+    let rate = cfg.sample_rate as u32;
+    let extension = extension(target_filename);
+    let mut output = if target_filename == STDOUT_NAME {
+        cfg.wav.streaming = true;
+        if cfg.raw {
+            Output::Raw(Box::new(std::io::stdout()))
+        } else {
+            if cfg.cue_points {
+                status!("Warning: cue points follow the samples, so they can't be streamed and have been left out");
             }
+            Output::Stream(wav::WavWriter::new(std::io::stdout(), rate, &cfg.wav)?)
+        }
+    } else if cfg.raw {
+        Output::Raw(Box::new(std::io::BufWriter::new(File::create(target_filename)?)))
+    } else {
+        match extension.as_str() {
+            "flac" | "aif" | "aiff" | "aifc" | "au" | "snd" => Output::Gather(vec![]),
+            _ => Output::Wav(wav::WavWriter::create(target_filename, rate, &cfg.wav)?),
+        }
+    };
+
+    // Each burst is equalized and written out as soon as it is modulated
+    let mut controller = cfg.make_controller();
+    controller.transmit_to(&input_data, &cfg.data_rate, cfg.repeat_count, |audio| match equalizer {
+        Some(ref mut equalizer) => output.write(&equalizer.process(audio), &cfg.wav),
+        None => output.write(audio, &cfg.wav),
+    })?;
+    if let Some(ref mut equalizer) = equalizer {
+        output.write(&equalizer.finish(), &cfg.wav)?;
+    }
+
+    if let Some(ref labels) = cfg.labels {
+        write_labels(labels, controller.markers(), cfg.sample_rate)?;
+    }
+    let cues = if cfg.cue_points {
+        make_cues(controller.markers())
+    } else {
+        vec![]
+    };
+
+    match output {
+        Output::Wav(mut writer) => {
+            writer.set_cues(cues);
+            writer.finalize()?;
+        }
+        Output::Stream(writer) => {
+            writer.finish_stream()?;
+        }
+        Output::Raw(mut file) => file.flush()?,
+        Output::Gather(audio_data) => {
+            cfg.wav.cues = cues;
+            match extension.as_str() {
+                "flac" => flac::write_flac(rate, &audio_data, &cfg.wav, target_filename)?,
+                "au" | "snd" => au::write_au(rate, &audio_data, &cfg.wav, target_filename)?,
+                _ => aiff::write_aiff(rate, &audio_data, &cfg.wav, target_filename)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+// Play the transmission through the default audio device.  It is
+// modulated on another thread a burst at a time, and resampled to the
+// device's rate as it goes.
+fn play(input_data: Vec<u8>, mut equalizer: Option<eq::Equalizer>, cfg: ModulationConfig) -> ! {
+    let endpoint = cpal::default_endpoint().expect("Failed to get default endpoint");
+    let format = endpoint
+        .supported_formats()
+        .unwrap()
+        .next()
+        .expect("Failed to get endpoint format")
+        .with_max_samples_rate();
+    status!("Format selected: {:?}", format);
+
+    // Always modulate at the same rate, and convert to whatever the
+    // device wants, so playback matches the WAV file exactly.
+    let device_rate = f64::from(format.samples_rate.0);
+    status!("Resampling from {} Hz to {} Hz", cfg.sample_rate, device_rate);
+
+    let layout = cfg.wav.layout;
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<Vec<f64>>(PLAYBACK_QUEUE);
+    std::thread::spawn(move || {
+        let mut controller = cfg.make_controller();
+        let mut resampler = resample::Resampler::new(cfg.sample_rate, device_rate);
+        let result = controller.transmit_to(&input_data, &cfg.data_rate, cfg.repeat_count, |audio| {
+            let audio = match equalizer {
+                Some(ref mut equalizer) => equalizer.process(audio),
+                None => audio.to_vec(),
+            };
+            sender.send(resampler.process(&audio))
+        });
+        // Sending only fails once playback has stopped
+        if result.is_err() {
+            return;
+        }
+        let mut rest = match equalizer {
+            Some(ref mut equalizer) => resampler.process(&equalizer.finish()),
+            None => vec![],
         };
+        rest.extend(resampler.finish());
+        if sender.send(rest).is_err() {
+            return;
+        }
 
-        event_loop.run(move |_, buffer| {
-            match buffer {
-                cpal::UnknownTypeBuffer::U16(mut buffer) => {
-                    for sample in buffer.chunks_mut(format.channels.len()) {
-                        let value = ((next_value() * 0.5 + 0.5) * std::u16::MAX as f32) as u16;
-                        for out in sample.iter_mut() {
-                            *out = value;
-                        }
-                    }
+        if let Some(ref labels) = cfg.labels {
+            if let Err(err) = write_labels(labels, controller.markers(), cfg.sample_rate) {
+                status!("Unable to modulate: {}", &err);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let event_loop = cpal::EventLoop::new();
+    let voice_id = event_loop.build_voice(&endpoint, &format).unwrap();
+    event_loop.play(voice_id);
+
+    let mut audio_data: Vec<f64> = vec![];
+    let mut audio_data_pos = 0;
+    let mut finished = false;
+    let mut overrun_count = 0;
+
+    let mut next_value = || {
+        // Wait for the next piece rather than play a gap, which would
+        // corrupt the packet being sent
+        while audio_data_pos >= audio_data.len() && !finished {
+            match receiver.recv() {
+                Ok(next) => {
+                    audio_data = next;
+                    audio_data_pos = 0;
                 }
+                Err(_) => finished = true,
+            }
+        }
+        if finished {
+            overrun_count += 1;
+            // After 250ms of silence, exit the program.
+            if overrun_count > (device_rate as u32 / 4) {
+                use std::process;
+                process::exit(0);
+            }
+            0.0
+        } else {
+            let val = audio_data[audio_data_pos];
+            audio_data_pos += 1;
+            val
+        }
+    };
 
-                cpal::UnknownTypeBuffer::I16(mut buffer) => {
-                    for sample in buffer.chunks_mut(format.channels.len()) {
-                        let value = (next_value() * std::i16::MAX as f32) as i16;
-                        for out in sample.iter_mut() {
-                            *out = value;
-                        }
+    event_loop.run(move |_, buffer| {
+        match buffer {
+            cpal::UnknownTypeBuffer::U16(mut buffer) => {
+                for sample in buffer.chunks_mut(format.channels.len()) {
+                    let value = next_value();
+                    for (channel, out) in sample.iter_mut().enumerate() {
+                        let value = layout.channel_value(value, channel) as f32;
+                        *out = ((value * 0.5 + 0.5) * std::u16::MAX as f32) as u16;
                     }
                 }
+            }
 
-                cpal::UnknownTypeBuffer::F32(mut buffer) => {
-                    for sample in buffer.chunks_mut(format.channels.len()) {
-                        let value = next_value();
-                        for out in sample.iter_mut() {
-                            *out = value;
-                        }
+            cpal::UnknownTypeBuffer::I16(mut buffer) => {
+                for sample in buffer.chunks_mut(format.channels.len()) {
+                    let value = next_value();
+                    for (channel, out) in sample.iter_mut().enumerate() {
+                        let value = layout.channel_value(value, channel) as f32;
+                        *out = (value * std::i16::MAX as f32) as i16;
                     }
                 }
-            };
-        });
-    } else {
-        let mut output: Vec<i16> = Vec::new();
-        for sample in audio_data {
-            // Map -1 .. 1 to -32767 .. 32768
-            output.push((sample * 32767.0).round() as i16);
-        }
+            }
 
-        wav::write_wav(cfg.sample_rate as u32, &output, target_filename)?;
-    }
-    Ok(())
+            cpal::UnknownTypeBuffer::F32(mut buffer) => {
+                for sample in buffer.chunks_mut(format.channels.len()) {
+                    let value = next_value();
+                    for (channel, out) in sample.iter_mut().enumerate() {
+                        *out = layout.channel_value(value, channel) as f32;
+                    }
+                }
+            }
+        };
+    });
 }
 
 fn main() -> Result<(), ModulationError> {
+    let preset_names = preset::names();
+    let eq_help = format!(
+        "Equalize for a speaker, either a device class ({}) or a file of \"frequency_hz gain_db\" measurements",
+        eq::PRESET_NAMES.join(", ")
+    );
+    let rate_help = format!(
+        "Audio encoding rate: high, mid, low, or a whole-number factor to slow the transmission by. \
+         Mid is a factor of 2 and low a factor of 4. The modulation and the gaps between packets \
+         are both stretched by the factor, and a factor of {} or more also sends a pilot tone \
+         after each pass",
+        controller::PILOT_MIN_STRETCH
+    );
     let matches = App::new("Love-to-Code Program Modulator")
         .version("1.3")
         .author("Sean Cross <sean@xobs.io>")
@@ -235,21 +507,66 @@ fn main() -> Result<(), ModulationError> {
                 .value_name("FILENAME")
                 .help("Name of the input file")
                 .takes_value(true)
-                .required(true),
+                .required_unless("suggest-params"),
         )
         .arg(
             Arg::with_name("output")
                 .short("o")
                 .long("output")
                 .value_name("FILENAME")
-                .help("Name of the wave file to write to"),
+                .help("Name of the wave file to write to.  Names ending in .flac, .aiff or .au write those formats instead, and - streams to stdout"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .possible_values(&wav::SAMPLE_FORMAT_NAMES)
+                .value_name("FORMAT")
+                .takes_value(true)
+                .default_value("s16")
+                .help("Sample format of the wave file: 8-bit unsigned, 16, 24 or 32-bit signed, or 32-bit float"),
+        )
+        .arg(
+            Arg::with_name("channels")
+                .long("channels")
+                .possible_values(&wav::CHANNEL_LAYOUT_NAMES)
+                .value_name("LAYOUT")
+                .takes_value(true)
+                .default_value("mono")
+                .help("Channel layout for the wave file and playback: mono, data on the left only, data on both, or differential"),
+        )
+        .arg(
+            Arg::with_name("cue-points")
+                .long("cue-points")
+                .help("Mark every packet, gap and repeat with cue points in the wave file"),
+        )
+        .arg(
+            Arg::with_name("labels")
+                .long("labels")
+                .value_name("FILENAME")
+                .takes_value(true)
+                .help("Also write the packet, gap and repeat positions to an Audacity label file"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .long("raw")
+                .help("Write bare samples with no header, in the chosen format, channels and rate"),
+        )
+        .arg(
+            Arg::with_name("rf64")
+                .long("rf64")
+                .help("Let the wave file grow past 4 GiB by writing it as RF64"),
+        )
+        .arg(
+            Arg::with_name("extensible")
+                .long("extensible")
+                .help("Write a WAVE_FORMAT_EXTENSIBLE header"),
         )
         .arg(
             Arg::with_name("sample-rate")
                 .short("r")
                 .long("rate")
                 .value_name("SAMPLE_RATE")
-                .help("Sample rate of the output file"),
+                .help("Sample rate to modulate at. Playback is resampled to suit the device"),
         )
         .arg(
             Arg::with_name("play")
@@ -287,11 +604,10 @@ fn main() -> Result<(), ModulationError> {
             Arg::with_name("encoding-rate")
                 .short("e")
                 .long("encoding-rate")
-                .possible_values(&["high", "mid", "low"])
                 .value_name("RATE")
                 .takes_value(true)
                 .default_value("high")
-                .help("Audio encoding rate"),
+                .help(&rate_help),
         )
         .arg(
             Arg::with_name("baud-rate")
@@ -299,8 +615,7 @@ fn main() -> Result<(), ModulationError> {
                 .long("baud")
                 .value_name("BAUD_RATE")
                 .takes_value(true)
-                .default_value("8000")
-                .help("Baud rate for transmission"),
+                .help("Baud rate for transmission, overriding the preset"),
         )
         .arg(
             Arg::with_name("f-lo")
@@ -309,8 +624,7 @@ fn main() -> Result<(), ModulationError> {
                 .aliases(&["flo", "f_lo", "f_low", "f-low", "flow", "f_space"])
                 .value_name("F_LO")
                 .takes_value(true)
-                .default_value("8666")
-                .help("Lower frequency used for F_LO / F_SPACE"),
+                .help("Lower frequency used for F_LO / F_SPACE, overriding the preset"),
         )
         .arg(
             Arg::with_name("f-hi")
@@ -319,76 +633,370 @@ fn main() -> Result<(), ModulationError> {
                 .aliases(&["fhi", "f_hi", "f_high", "f-high", "f_mark"])
                 .value_name("F_HI")
                 .takes_value(true)
-                .default_value("12500")
-                .help("Lower frequency used for F_HI / F_MARK"),
+                .help("Lower frequency used for F_HI / F_MARK, overriding the preset"),
+        )
+        .arg(
+            Arg::with_name("tones")
+                .long("tones")
+                .value_name("F0,F1,...")
+                .takes_value(true)
+                .use_delimiter(true)
+                .help("Comma-separated tone list for M-FSK, replacing F_LO and F_HI. Must have 2, 4, 8... entries"),
+        )
+        .arg(
+            Arg::with_name("framing")
+                .long("framing")
+                .possible_values(&["raw", "async"])
+                .value_name("FRAMING")
+                .takes_value(true)
+                .help("Send bytes raw, or wrapped in UART-style start/stop bits, overriding the preset"),
+        )
+        .arg(
+            Arg::with_name("stop-bits")
+                .long("stop-bits")
+                .possible_values(&["1", "2"])
+                .value_name("COUNT")
+                .takes_value(true)
+                .help("Number of stop bits used with async framing"),
+        )
+        .arg(
+            Arg::with_name("gaussian-bt")
+                .long("gaussian-bt")
+                .value_name("BT")
+                .takes_value(true)
+                .help("Smooth tone transitions with a Gaussian filter of this BT product (GFSK)"),
+        )
+        .arg(
+            Arg::with_name("fixed-point")
+                .long("fixed-point")
+                .conflicts_with("gaussian-bt")
+                .help("Generate FSK with the integer sine-table encoder used on microcontrollers"),
+        )
+        .arg(
+            Arg::with_name("modulation")
+                .long("modulation")
+                .possible_values(&["fsk", "dbpsk", "dqpsk", "ofdm"])
+                .value_name("SCHEME")
+                .takes_value(true)
+                .default_value("fsk")
+                .help("Modulation scheme"),
+        )
+        .arg(
+            Arg::with_name("carrier")
+                .long("carrier")
+                .value_name("FREQ")
+                .takes_value(true)
+                .help("Carrier frequency for PSK, or centre frequency for OFDM, defaulting to halfway between F_LO and F_HI"),
+        )
+        .arg(
+            Arg::with_name("ofdm-subcarriers")
+                .long("ofdm-subcarriers")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value("48")
+                .help("Number of OFDM subcarriers, including pilots"),
+        )
+        .arg(
+            Arg::with_name("ofdm-fft-size")
+                .long("ofdm-fft-size")
+                .value_name("POINTS")
+                .takes_value(true)
+                .default_value("256")
+                .help("OFDM FFT size, which sets the subcarrier spacing"),
+        )
+        .arg(
+            Arg::with_name("ofdm-cyclic-prefix")
+                .long("ofdm-cyclic-prefix")
+                .value_name("SAMPLES")
+                .takes_value(true)
+                .default_value("32")
+                .help("Length of the OFDM cyclic prefix"),
+        )
+        .arg(
+            Arg::with_name("ofdm-pilot-spacing")
+                .long("ofdm-pilot-spacing")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value("8")
+                .help("Send a pilot tone on every Nth OFDM subcarrier"),
+        )
+        .arg(
+            Arg::with_name("fade-in")
+                .long("fade-in")
+                .value_name("MSECS")
+                .takes_value(true)
+                .default_value("0")
+                .help("Length of the raised-cosine fade-in on carrier sent before each packet"),
+        )
+        .arg(
+            Arg::with_name("fade-out")
+                .long("fade-out")
+                .value_name("MSECS")
+                .takes_value(true)
+                .default_value("0")
+                .help("Length of the raised-cosine fade-out on carrier sent after each packet"),
+        )
+        .arg(
+            Arg::with_name("gain")
+                .long("gain")
+                .value_name("GAIN")
+                .takes_value(true)
+                .default_value("1.0")
+                .help("Output level, from 0.0 to 1.0 of full scale"),
+        )
+        .arg(
+            Arg::with_name("eq")
+                .long("eq")
+                .value_name("DEVICE|FILENAME")
+                .takes_value(true)
+                .help(&eq_help),
+        )
+        .arg(
+            Arg::with_name("preset")
+                .short("m")
+                .long("preset")
+                .possible_values(&preset_names)
+                .value_name("PRESET")
+                .takes_value(true)
+                .default_value(preset::DEFAULT_PRESET)
+                .help("Modem preset setting the baud rate, tones and framing"),
+        )
+        .arg(
+            Arg::with_name("auto-params")
+                .long("auto-params")
+                .value_name("TARGET_BAUD")
+                .takes_value(true)
+                .conflicts_with("preset")
+                .help("Pick the baud rate and tones to suit the output sample rate, up to this baud rate"),
+        )
+        .arg(
+            Arg::with_name("suggest-params")
+                .long("suggest-params")
+                .value_name("TARGET_BAUD")
+                .takes_value(true)
+                .help("Print the settings --auto-params would pick, and exit"),
         )
         .get_matches();
 
-    let source_filename = matches.value_of("input").unwrap();
     let target_filename = matches.value_of("output").unwrap_or("output.wav");
+    if target_filename == STDOUT_NAME {
+        STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+    }
     let os_update = matches.is_present("update");
     let play_file = matches.is_present("play");
     let repeats = matches.value_of("repeats").unwrap().parse::<u32>().unwrap();
-    let output_sample_rate = if play_file {
-        let endpoint = cpal::default_endpoint().expect("Failed to get default endpoint");
-        let format = endpoint
-            .supported_formats()
-            .unwrap()
-            .next()
-            .expect("Failed to get endpoint format")
-            .with_max_samples_rate();
-        format.samples_rate.0 as f64
-    } else {
-        matches
-            .value_of("sample-rate")
-            .map(|s| s.parse::<f64>().unwrap())
-            .unwrap_or(DEFAULT_SAMPLE_RATE)
+    let output_sample_rate = matches
+        .value_of("sample-rate")
+        .map(|s| s.parse::<f64>().unwrap())
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+
+    if let Some(target) = matches.value_of("suggest-params") {
+        match preset::suggest(output_sample_rate, target.parse::<f64>()?) {
+            Some(p) => {
+                println!("{}", p);
+                println!("{}", p.command_line());
+            }
+            None => println!("No suitable settings found for {} Hz", output_sample_rate),
+        }
+        return Ok(());
+    }
+    let source_filename = matches.value_of("input").unwrap();
+
+    let auto_preset;
+    let preset = match matches.value_of("auto-params") {
+        Some(target) => {
+            auto_preset = preset::suggest(output_sample_rate, target.parse::<f64>()?)
+                .expect("No suitable settings found for the sample rate");
+            &auto_preset
+        }
+        None => preset::find(matches.value_of("preset").unwrap()).unwrap(),
     };
     let baud_rate = matches
         .value_of("baud-rate")
         .map(|s| s.parse::<f64>())
-        .unwrap()?;
+        .unwrap_or(Ok(preset.baud_rate))?;
     let f_lo = matches
         .value_of("f-lo")
         .map(|s| s.parse::<f64>())
-        .unwrap()?;
+        .unwrap_or(Ok(preset.f_lo))?;
     let f_hi = matches
         .value_of("f-hi")
         .map(|s| s.parse::<f64>())
-        .unwrap()?;
+        .unwrap_or(Ok(preset.f_hi))?;
+    let tones = match matches.values_of("tones") {
+        Some(values) => {
+            let mut tones = vec![];
+            for value in values {
+                tones.push(value.parse::<f64>()?);
+            }
+            if tones.len() < 2 || !tones.len().is_power_of_two() {
+                panic!("Number of tones must be a power of two, but {} were given", tones.len());
+            }
+            tones
+        }
+        None => vec![f_lo, f_hi],
+    };
     let protocol_version = match matches.value_of("version") {
         Some("1") => controller::ProtocolVersion::V1,
         Some("2") => controller::ProtocolVersion::V2,
         Some(x) => panic!("Unrecognized version found: {}", x),
         None => panic!("No protocol version specified"),
     };
+    let stop_bits = match matches.value_of("stop-bits") {
+        Some(s) => s.parse::<u8>().unwrap(),
+        None => match preset.framing {
+            fsk::Framing::Async { stop_bits } => stop_bits,
+            fsk::Framing::Raw => 1,
+        },
+    };
+    let framing = match matches.value_of("framing") {
+        Some("raw") => fsk::Framing::Raw,
+        Some("async") => fsk::Framing::Async { stop_bits },
+        Some(x) => panic!("Unrecognized framing found: {}", x),
+        None => match preset.framing {
+            fsk::Framing::Raw => fsk::Framing::Raw,
+            fsk::Framing::Async { .. } => fsk::Framing::Async { stop_bits },
+        },
+    };
+    let gaussian_bt = match matches.value_of("gaussian-bt") {
+        Some(s) => {
+            let bt = s.parse::<f64>()?;
+            if bt <= 0.0 {
+                panic!("Gaussian BT must be greater than zero");
+            }
+            Some(bt)
+        }
+        None => None,
+    };
+    let psk_mode = match matches.value_of("modulation") {
+        Some("fsk") => None,
+        Some("dbpsk") => Some(psk::PskMode::Dbpsk),
+        Some("dqpsk") => Some(psk::PskMode::Dqpsk),
+        Some("ofdm") => None,
+        Some(x) => panic!("Unrecognized modulation found: {}", x),
+        None => panic!("No modulation specified"),
+    };
+    // These only shape FSK, so don't let them be silently ignored
+    if matches.value_of("modulation") != Some("fsk") {
+        for flag in &["framing", "stop-bits", "gaussian-bt", "fixed-point"] {
+            if matches.is_present(flag) {
+                panic!("--{} only applies to FSK modulation", flag);
+            }
+        }
+    }
+    let carrier = matches
+        .value_of("carrier")
+        .map(|s| s.parse::<f64>())
+        .unwrap_or(Ok((f_lo + f_hi) / 2.0))?;
+    let ofdm = if matches.value_of("modulation") == Some("ofdm") {
+        let parse_usize = |name| matches.value_of(name).unwrap().parse::<usize>();
+        Some(OfdmConfig {
+            fft_size: parse_usize("ofdm-fft-size")?,
+            subcarriers: parse_usize("ofdm-subcarriers")?,
+            cyclic_prefix: parse_usize("ofdm-cyclic-prefix")?,
+            pilot_spacing: parse_usize("ofdm-pilot-spacing")?,
+        })
+    } else {
+        None
+    };
+    let envelope = controller::Envelope {
+        fade_in_ms: matches.value_of("fade-in").unwrap().parse::<f64>()?,
+        fade_out_ms: matches.value_of("fade-out").unwrap().parse::<f64>()?,
+        gain: matches.value_of("gain").unwrap().parse::<f64>()?,
+    };
+    if envelope.gain < 0.0 || envelope.gain > 1.0 {
+        panic!("Gain must be between 0.0 and 1.0");
+    }
     let data_rate = match matches.value_of("encoding-rate") {
-        Some("low") => EncodingRate::Low,
-        Some("mid") => EncodingRate::Mid,
-        Some("high") => EncodingRate::High,
-        Some(x) => panic!("Unrecognized rate found: {}", x),
+        Some(x) => match EncodingRate::parse(x) {
+            Some(rate) => rate,
+            None => panic!("Unrecognized rate found: {}", x),
+        },
         None => panic!("No valid rate specified"),
     };
 
-    println!("Modulating {} into {}.", source_filename, target_filename);
-    println!(
-        "Is update? {}  Data rate: {}  Protocol version: {:?}",
-        os_update, data_rate, protocol_version
+    // Check the settings at the rate the modulator actually runs at
+    let modulation_rate = data_rate.modulation_rate(output_sample_rate);
+    let warnings = if let Some(ref ofdm) = ofdm {
+        validate::check_ofdm(modulation_rate, &ofdm.params(modulation_rate, carrier))?
+    } else if psk_mode.is_some() {
+        validate::check_carrier(modulation_rate, baud_rate, carrier)?
+    } else {
+        validate::check_fsk(modulation_rate, baud_rate, &tones)?
+    };
+    // A standard preset's tones are what they are, so only warn about
+    // them if they have been changed
+    let standard_tones = preset.standard
+        && !["baud-rate", "f-lo", "f-hi", "tones"].iter().any(|arg| matches.is_present(arg));
+    for warning in warnings {
+        if !(standard_tones && warning.is_about_tones()) {
+            status!("Warning: {}", warning);
+        }
+    }
+
+    status!(
+        "Modulating {} into {}.",
+        source_filename,
+        if target_filename == STDOUT_NAME { "standard output" } else { target_filename }
     );
+    status!(
+        "Is update? {}  Data rate: {}  Protocol version: {:?}  Framing: {}",
+        os_update, data_rate, protocol_version, framing
+    );
+    status!(
+        "Preset: {} ({})  Baud rate: {}  Tones: {:?}",
+        preset.name, preset.description, baud_rate, tones
+    );
+    if data_rate.stretch() > 1 {
+        status!(
+            "Stretched {}x to {} baud at {} Hz",
+            data_rate.stretch(),
+            baud_rate / f64::from(data_rate.stretch()),
+            output_sample_rate
+        );
+    }
+    if let Some(mode) = psk_mode {
+        status!("Modulation: {}  Carrier: {}", mode, carrier);
+    }
+    if ofdm.is_some() {
+        status!("Modulation: OFDM  Centre frequency: {}", carrier);
+    }
+    if let Some(bt) = gaussian_bt {
+        status!("Gaussian frequency shaping, BT = {}", bt);
+    }
 
     let cfg = ModulationConfig {
         data_rate,
         os_update,
         baud_rate,
-        f_lo,
-        f_hi,
+        tones,
+        framing,
+        gaussian_bt,
+        fixed_point: matches.is_present("fixed-point"),
+        psk_mode,
+        carrier,
+        ofdm,
+        envelope,
+        eq: matches.value_of("eq").map(|s| s.to_owned()),
+        wav: wav::WavOptions {
+            format: wav::SampleFormat::parse(matches.value_of("format").unwrap()).unwrap(),
+            layout: wav::ChannelLayout::parse(matches.value_of("channels").unwrap()).unwrap(),
+            extensible: matches.is_present("extensible"),
+            cues: vec![],
+            info: vec![],
+            streaming: false,
+            rf64: matches.is_present("rf64"),
+        },
+        cue_points: matches.is_present("cue-points"),
+        labels: matches.value_of("labels").map(|s| s.to_owned()),
+        raw: matches.is_present("raw"),
         version: protocol_version,
         repeat_count: repeats,
         sample_rate: output_sample_rate,
     };
 
     if let Err(err) = do_modulation(source_filename, target_filename, play_file, cfg) {
-        println!("Unable to modulate: {}", &err);
+        status!("Unable to modulate: {}", &err);
         std::process::exit(1);
     }
 
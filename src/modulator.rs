@@ -1,16 +1,87 @@
 use fsk;
+use fsk_fixed;
+use ofdm;
+use psk;
 
-pub struct Modulator {
-    encoder: fsk::FskEncoder,
+/// Anything that can turn packet bytes into audio.  The `Controller` builds
+/// the packets and leaves the choice of modulation scheme to this trait.
+pub trait Modulate {
+    // Modulate an array of 8-bit bytes into samples in the range -1.0 .. 1.0
+    fn modulate_pcm(&mut self, input: &[u8]) -> Vec<f64>;
+
+    // Four-bit code telling the receiver how the packet was modulated.
+    // The top bit is set for PSK, and the rest hold the number of bits
+    // per symbol beyond the first.  Binary FSK is always 0, and 0xf is
+    // reserved for OFDM.  FSK and PSK send everything up to and including
+    // the version byte one bit per symbol, so the code can be read before
+    // the symbol size changes.
+    fn mode(&self) -> u8 {
+        0
+    }
+
+    // Steady, unmodulated carrier to pad either side of a burst, carrying
+    // on from where the last samples left off.  `lead_in` is set for the
+    // padding before a burst.  Schemes without a single carrier to hold
+    // pad with silence.
+    fn carrier(&mut self, samples: usize, _lead_in: bool) -> Vec<f64> {
+        vec![0.0; samples]
+    }
+}
+
+impl Modulate for fsk::FskEncoder {
+    fn modulate_pcm(&mut self, input: &[u8]) -> Vec<f64> {
+        self.modulate(input)
+    }
+
+    fn mode(&self) -> u8 {
+        (self.bits_per_symbol() - 1) as u8
+    }
+
+    fn carrier(&mut self, samples: usize, lead_in: bool) -> Vec<f64> {
+        fsk::FskEncoder::carrier(self, samples, lead_in)
+    }
+}
+
+impl Modulate for fsk_fixed::FixedFskEncoder {
+    fn modulate_pcm(&mut self, input: &[u8]) -> Vec<f64> {
+        self.modulate(input)
+            .iter()
+            .map(|sample| f64::from(*sample) / 32767.0)
+            .collect()
+    }
+
+    fn mode(&self) -> u8 {
+        (self.bits_per_symbol() - 1) as u8
+    }
+
+    fn carrier(&mut self, samples: usize, lead_in: bool) -> Vec<f64> {
+        fsk_fixed::FixedFskEncoder::carrier(self, samples, lead_in)
+            .iter()
+            .map(|sample| f64::from(*sample) / 32767.0)
+            .collect()
+    }
+}
+
+impl Modulate for psk::PskEncoder {
+    fn modulate_pcm(&mut self, input: &[u8]) -> Vec<f64> {
+        self.modulate(input)
+    }
+
+    fn mode(&self) -> u8 {
+        0x8 | (self.bits_per_symbol() - 1) as u8
+    }
+
+    fn carrier(&mut self, samples: usize, _lead_in: bool) -> Vec<f64> {
+        psk::PskEncoder::carrier(self, samples)
+    }
 }
 
-impl Modulator {
-    pub fn new(sample_rate: f64, baud_rate: f64, f_lo: f64, f_hi: f64) -> Modulator {
-        Modulator { encoder: fsk::FskEncoder::new(f_lo, f_hi, baud_rate, sample_rate) }
+impl Modulate for ofdm::OfdmEncoder {
+    fn modulate_pcm(&mut self, input: &[u8]) -> Vec<f64> {
+        self.modulate(input)
     }
 
-    // Modulate an array of 8-bit bytes into an array of signed 16-bit PCM samples
-    pub fn modulate_pcm(&mut self, input: &[u8]) -> Vec<f64> {
-        self.encoder.modulate(input)
+    fn mode(&self) -> u8 {
+        0xf
     }
 }
@@ -0,0 +1,303 @@
+use std;
+use std::f64;
+
+/// Layout of an OFDM symbol
+#[derive(Clone, Copy, Debug)]
+pub struct OfdmParams {
+    /// Number of points in the inverse FFT.  Must be a power of two.
+    pub fft_size: usize,
+
+    /// Number of subcarriers in use, including pilots
+    pub subcarriers: usize,
+
+    /// FFT bin of the lowest subcarrier
+    pub first_bin: usize,
+
+    /// Number of samples copied from the end of each symbol to its start
+    pub cyclic_prefix: usize,
+
+    /// Every Nth subcarrier carries a known pilot rather than data
+    pub pilot_spacing: usize,
+}
+
+impl OfdmParams {
+    /// Centre the subcarriers on `carrier` Hz
+    pub fn centred(
+        sample_rate: f64,
+        carrier: f64,
+        fft_size: usize,
+        subcarriers: usize,
+        cyclic_prefix: usize,
+        pilot_spacing: usize,
+    ) -> OfdmParams {
+        let spacing = sample_rate / fft_size as f64;
+        let centre_bin = (carrier / spacing).round() as usize;
+        OfdmParams {
+            fft_size,
+            subcarriers,
+            first_bin: centre_bin.saturating_sub(subcarriers / 2).max(1),
+            cyclic_prefix,
+            pilot_spacing,
+        }
+    }
+
+    /// Frequency of the highest subcarrier, in Hz
+    pub fn top_frequency(&self, sample_rate: f64) -> f64 {
+        (self.first_bin + self.subcarriers).saturating_sub(1) as f64 * sample_rate / self.fft_size as f64
+    }
+
+    fn is_pilot(&self, subcarrier: usize) -> bool {
+        subcarrier % self.pilot_spacing == 0
+    }
+
+    /// Number of bits carried by each OFDM symbol
+    pub fn bits_per_symbol(&self) -> usize {
+        let pilots = (self.subcarriers + self.pilot_spacing - 1) / self.pilot_spacing;
+        (self.subcarriers - pilots) * 2
+    }
+}
+
+impl std::fmt::Display for OfdmParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} subcarriers from bin {} of {}, {}-sample cyclic prefix, pilot every {}",
+            self.subcarriers, self.first_bin, self.fft_size, self.cyclic_prefix, self.pilot_spacing
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+// Value sent on every pilot subcarrier
+const PILOT: Complex = Complex { re: 1.0, im: 0.0 };
+
+// Target RMS level of the output, leaving headroom for OFDM's high
+// peak-to-average ratio.  The rare peaks beyond full scale are clipped.
+const RMS_LEVEL: f64 = 0.25;
+
+/// Experimental OFDM modulator for clean, wideband links such as a
+/// line-out cable.  Data subcarriers carry Gray-coded QPSK, and every
+/// burst starts with a training symbol whose two halves are identical,
+/// which a receiver can use to find symbol timing.
+pub struct OfdmEncoder {
+    params: OfdmParams,
+    scale: f64,
+}
+
+impl OfdmEncoder {
+    pub fn new(params: OfdmParams) -> OfdmEncoder {
+        assert!(params.fft_size.is_power_of_two(), "OFDM FFT size must be a power of two");
+        assert!(params.pilot_spacing > 0, "OFDM pilot spacing must be at least 1");
+        assert!(
+            params.cyclic_prefix <= params.fft_size,
+            "OFDM cyclic prefix must be no longer than the FFT"
+        );
+        assert!(
+            params.first_bin + params.subcarriers <= params.fft_size / 2,
+            "OFDM subcarriers must sit below Nyquist"
+        );
+        assert!(params.bits_per_symbol() > 0, "OFDM symbols must carry some data");
+
+        // Each active bin and its mirror image contribute 1 / N^2 of power
+        let power = 2.0 * params.subcarriers as f64 / (params.fft_size * params.fft_size) as f64;
+        OfdmEncoder {
+            params,
+            scale: RMS_LEVEL / power.sqrt(),
+        }
+    }
+
+    pub fn modulate(&mut self, input: &[u8]) -> Vec<f64> {
+        let bits_per_symbol = self.params.bits_per_symbol();
+        let total_bits = input.len() * 8;
+        let symbols = (total_bits + bits_per_symbol - 1) / bits_per_symbol;
+        let mut output = Vec::with_capacity(
+            (symbols + 1) * (self.params.fft_size + self.params.cyclic_prefix),
+        );
+
+        let training = self.training_bins();
+        self.append_symbol(&training, &mut output);
+
+        let mut bit_pos = 0;
+        let mut next_bit = || {
+            let bit = if bit_pos < total_bits {
+                (input[bit_pos / 8] >> (bit_pos % 8)) & 1
+            } else {
+                0
+            };
+            bit_pos += 1;
+            bit
+        };
+
+        for _ in 0..symbols {
+            let mut bins = vec![Complex::new(0.0, 0.0); self.params.fft_size];
+            for subcarrier in 0..self.params.subcarriers {
+                let bin = self.params.first_bin + subcarrier;
+                bins[bin] = if self.params.is_pilot(subcarrier) {
+                    PILOT
+                } else {
+                    let b0 = next_bit();
+                    let b1 = next_bit();
+                    qpsk(b0, b1)
+                };
+            }
+            self.append_symbol(&bins, &mut output);
+        }
+        output
+    }
+
+    // Pilots on even subcarriers only, so the symbol repeats every half
+    fn training_bins(&self) -> Vec<Complex> {
+        let mut bins = vec![Complex::new(0.0, 0.0); self.params.fft_size];
+        for subcarrier in 0..self.params.subcarriers {
+            let bin = self.params.first_bin + subcarrier;
+            if bin % 2 == 0 {
+                bins[bin] = Complex::new(f64::consts::SQRT_2, 0.0);
+            }
+        }
+        bins
+    }
+
+    fn append_symbol(&self, bins: &[Complex], output: &mut Vec<f64>) {
+        let n = self.params.fft_size;
+
+        // Mirror the positive frequencies so the result is real
+        let mut spectrum = bins.to_vec();
+        for k in 1..n / 2 {
+            spectrum[n - k] = Complex::new(bins[k].re, -bins[k].im);
+        }
+        inverse_fft(&mut spectrum);
+
+        let symbol: Vec<f64> = spectrum
+            .iter()
+            .map(|c| (c.re * self.scale).clamp(-1.0, 1.0))
+            .collect();
+        output.extend_from_slice(&symbol[n - self.params.cyclic_prefix..]);
+        output.extend_from_slice(&symbol);
+    }
+}
+
+// Gray-coded QPSK constellation point with unit magnitude
+fn qpsk(b0: u8, b1: u8) -> Complex {
+    let level = f64::consts::FRAC_1_SQRT_2;
+    Complex::new(
+        if b0 == 0 { level } else { -level },
+        if b1 == 0 { level } else { -level },
+    )
+}
+
+// In-place radix-2 inverse FFT, scaled by 1/N
+fn inverse_fft(data: &mut [Complex]) {
+    let n = data.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = 2.0 * f64::consts::PI / len as f64;
+        let step = Complex::new(angle.cos(), angle.sin());
+        for start in (0..n).step_by(len) {
+            let mut w = Complex::new(1.0, 0.0);
+            for k in 0..len / 2 {
+                let a = data[start + k];
+                let b = data[start + k + len / 2].mul(w);
+                data[start + k] = Complex::new(a.re + b.re, a.im + b.im);
+                data[start + k + len / 2] = Complex::new(a.re - b.re, a.im - b.im);
+                w = w.mul(step);
+            }
+        }
+        len <<= 1;
+    }
+
+    let scale = 1.0 / n as f64;
+    for c in data.iter_mut() {
+        c.re *= scale;
+        c.im *= scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Skip the training symbol, then read QPSK back off every data
+    // subcarrier with a plain DFT
+    fn demodulate(params: &OfdmParams, samples: &[f64]) -> Vec<u8> {
+        let n = params.fft_size;
+        let symbol_len = n + params.cyclic_prefix;
+        let mut bits = vec![];
+        for symbol in samples.chunks(symbol_len).skip(1) {
+            let symbol = &symbol[params.cyclic_prefix..];
+            for subcarrier in (0..params.subcarriers).filter(|s| !params.is_pilot(*s)) {
+                let omega = 2.0 * f64::consts::PI * (params.first_bin + subcarrier) as f64 / n as f64;
+                let (re, im) = symbol.iter().enumerate().fold((0.0, 0.0), |(re, im), (t, s)| {
+                    (re + s * (omega * t as f64).cos(), im - s * (omega * t as f64).sin())
+                });
+                bits.push((re < 0.0) as u8);
+                bits.push((im < 0.0) as u8);
+            }
+        }
+        bits.chunks(8)
+            .filter(|byte| byte.len() == 8)
+            .map(|byte| byte.iter().rev().fold(0, |acc, bit| (acc << 1) | bit))
+            .collect()
+    }
+
+    #[test]
+    fn loopback() {
+        let params = OfdmParams::centred(48000.0, 12000.0, 64, 16, 16, 4);
+        let input: Vec<u8> = (0..=255).collect();
+        let samples = OfdmEncoder::new(params).modulate(&input);
+
+        // One training symbol, then just enough to hold the data
+        assert_eq!(samples.len() % (64 + 16), 0);
+        let symbols = samples.len() / (64 + 16) - 1;
+        assert!(symbols * params.bits_per_symbol() >= input.len() * 8);
+        assert!((symbols - 1) * params.bits_per_symbol() < input.len() * 8);
+        assert_eq!(&demodulate(&params, &samples)[..input.len()], &input[..]);
+    }
+
+    // Every subcarrier up to just below Nyquist is allowed
+    #[test]
+    fn top_subcarrier() {
+        let params = OfdmParams {
+            fft_size: 64,
+            subcarriers: 31,
+            first_bin: 1,
+            cyclic_prefix: 64,
+            pilot_spacing: 1000,
+        };
+        assert_eq!(params.top_frequency(48000.0), 23250.0);
+        let input = [0x5a; 16];
+        let samples = OfdmEncoder::new(params).modulate(&input);
+        assert_eq!(&demodulate(&params, &samples)[..input.len()], &input[..]);
+    }
+}
@@ -0,0 +1,152 @@
+use fsk;
+use std;
+
+/// A named set of modem parameters, selected in one step
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub baud_rate: f64,
+
+    /// Tone used for a 0 bit (space)
+    pub f_lo: f64,
+
+    /// Tone used for a 1 bit (mark)
+    pub f_hi: f64,
+    pub framing: fsk::Framing,
+
+    /// The settings follow an established standard, so warnings about the
+    /// choice of tones are not shown for them
+    pub standard: bool,
+}
+
+pub const DEFAULT_PRESET: &str = "ltc-default";
+
+pub static PRESETS: [Preset; 3] = [
+    Preset {
+        name: "ltc-default",
+        description: "Love-to-Code sticker, 8000 baud",
+        baud_rate: 8000.0,
+        f_lo: 8666.0,
+        f_hi: 12500.0,
+        framing: fsk::Framing::Raw,
+        standard: true,
+    },
+    // Bell 202 marks with the lower tone, so the space tone is the higher one.
+    Preset {
+        name: "bell202",
+        description: "Bell 202 / AFSK1200, 1200 baud, 8N1",
+        baud_rate: 1200.0,
+        f_lo: 2200.0,
+        f_hi: 1200.0,
+        framing: fsk::Framing::Async { stop_bits: 1 },
+        standard: true,
+    },
+    Preset {
+        name: "kcs",
+        description: "Kansas City Standard, 300 baud, 8N2",
+        baud_rate: 300.0,
+        f_lo: 1200.0,
+        f_hi: 2400.0,
+        framing: fsk::Framing::Async { stop_bits: 2 },
+        standard: true,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    PRESETS.iter().map(|p| p.name).collect()
+}
+
+// Tones are placed as close to this fraction of the sample rate as the
+// grid allows, which is roughly where the default tones sit at 44.1 kHz.
+const TONE_FRACTION: f64 = 0.3;
+
+// Fewest whole samples per bit that a suggestion will use
+const MIN_SAMPLES_PER_BIT: u32 = 4;
+
+/// Propose settings for `sample_rate` with a baud rate no higher than
+/// `target_baud`.  Each bit is a whole number of samples, and the tones
+/// sit on a grid of half the baud rate, spaced half the baud rate apart
+/// (MSK), so they are orthogonal and every bit ends on a whole number of
+/// half-cycles.
+pub fn suggest(sample_rate: f64, target_baud: f64) -> Option<Preset> {
+    if !(sample_rate > 0.0 && target_baud > 0.0) {
+        return None;
+    }
+    let samples_per_bit = ((sample_rate / target_baud).ceil() as u32).max(MIN_SAMPLES_PER_BIT);
+    let baud_rate = sample_rate / f64::from(samples_per_bit);
+    let step = baud_rate / 2.0;
+    let f_hi = (sample_rate * TONE_FRACTION / step).floor().max(2.0) * step;
+    Some(Preset {
+        name: "auto",
+        description: "Suggested for the sample rate",
+        baud_rate,
+        f_lo: f_hi - step,
+        f_hi,
+        framing: fsk::Framing::Raw,
+        standard: false,
+    })
+}
+
+impl Preset {
+    /// The command-line flags that select this preset's settings
+    pub fn command_line(&self) -> String {
+        let framing = match self.framing {
+            fsk::Framing::Raw => "--framing raw".to_owned(),
+            fsk::Framing::Async { stop_bits } => format!("--framing async --stop-bits {}", stop_bits),
+        };
+        format!(
+            "--baud {} --f-lo {} --f-hi {} {}",
+            self.baud_rate, self.f_lo, self.f_hi, framing
+        )
+    }
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}): {} baud, F_LO {} Hz, F_HI {} Hz, {} framing",
+            self.name, self.description, self.baud_rate, self.f_lo, self.f_hi, self.framing
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validate;
+
+    #[test]
+    fn presets_pass_validation() {
+        for preset in PRESETS.iter() {
+            for sample_rate in &[44100.0, 48000.0] {
+                let tones = [preset.f_lo, preset.f_hi];
+                let warnings = validate::check_fsk(*sample_rate, preset.baud_rate, &tones).unwrap();
+                assert!(
+                    warnings.iter().all(|w| w.is_about_tones()),
+                    "{} at {} Hz: {:?}",
+                    preset.name,
+                    sample_rate,
+                    warnings
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn suggestions_have_no_warnings() {
+        for sample_rate in &[8000.0, 22050.0, 44100.0, 48000.0, 96000.0] {
+            for target_baud in &[300.0, 1200.0, 4800.0, 9600.0] {
+                let preset = suggest(*sample_rate, *target_baud).unwrap();
+                assert_eq!(preset.baud_rate * (sample_rate / preset.baud_rate).round(), *sample_rate);
+                let tones = [preset.f_lo, preset.f_hi];
+                let warnings = validate::check_fsk(*sample_rate, preset.baud_rate, &tones).unwrap();
+                assert!(warnings.is_empty(), "{} Hz, {} baud: {:?}", sample_rate, target_baud, warnings);
+            }
+        }
+    }
+}
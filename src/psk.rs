@@ -0,0 +1,260 @@
+use std;
+use std::f64;
+
+use ltc_core::packet::MODE_HEADER_LEN;
+
+/// Which flavour of differential phase-shift keying to use
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PskMode {
+    /// One bit per symbol, sent as a 0 or 180 degree phase change
+    Dbpsk,
+
+    /// Two bits per symbol, sent as a Gray-coded multiple of 90 degrees
+    Dqpsk,
+}
+
+impl PskMode {
+    pub fn bits_per_symbol(self) -> u32 {
+        match self {
+            PskMode::Dbpsk => 1,
+            PskMode::Dqpsk => 2,
+        }
+    }
+
+    // Number of quarter turns the carrier advances for a given symbol
+    fn quadrant(self, symbol: u32) -> u32 {
+        match self {
+            PskMode::Dbpsk => symbol * 2,
+            PskMode::Dqpsk => GRAY_QUADRANTS[symbol as usize],
+        }
+    }
+
+    // Turn a measured phase change back into a symbol
+    fn symbol(self, delta: f64) -> u32 {
+        let quarter = f64::consts::PI / 2.0;
+        match self {
+            PskMode::Dbpsk => if delta.abs() > quarter { 1 } else { 0 },
+            PskMode::Dqpsk => {
+                let quadrant = ((delta / quarter).round() as i32).rem_euclid(4);
+                GRAY_QUADRANTS[quadrant as usize]
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PskMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            PskMode::Dbpsk => write!(f, "DBPSK"),
+            PskMode::Dqpsk => write!(f, "DQPSK"),
+        }
+    }
+}
+
+// Gray code, so that a one-quadrant error only flips a single bit.
+// The mapping is its own inverse.
+const GRAY_QUADRANTS: [u32; 4] = [0, 1, 3, 2];
+
+pub struct PskEncoder {
+    mode: PskMode,
+    baud_frac: f64,
+    baud_incr: f64,
+    phase: f64,
+    omega: f64,
+    symbol_phase: f64,
+
+    current_byte: u8,
+    bit_pos: u32,
+    data_pos: usize,
+}
+
+impl PskEncoder {
+    pub fn new(mode: PskMode, carrier: f64, baud_rate: f64, sample_rate: f64) -> PskEncoder {
+        PskEncoder {
+            mode,
+            baud_frac: 0.0,
+            baud_incr: baud_rate / sample_rate,
+            phase: 0.0,
+            omega: (2.0 * f64::consts::PI * carrier) / sample_rate,
+            symbol_phase: 0.0,
+
+            current_byte: 0,
+            bit_pos: 0,
+            data_pos: 0,
+        }
+    }
+
+    pub fn bits_per_symbol(&self) -> u32 {
+        self.mode.bits_per_symbol()
+    }
+
+    // Each symbol is sent as a phase change relative to the one before it,
+    // so the receiver never needs to recover the absolute carrier phase.
+    pub fn modulate(&mut self, input: &[u8]) -> Vec<f64> {
+        let mut output: Vec<f64> = Vec::new();
+        self.data_pos = 0;
+
+        loop {
+            self.baud_frac += self.baud_incr;
+            if self.baud_frac >= 1.0 {
+                self.baud_frac -= 1.0;
+                let in_header = self.in_mode_header();
+                let mut symbol = match self.next_bit(input) {
+                    Some(bit) => bit,
+                    None => return output,
+                };
+                // The header is always DBPSK
+                let mode = if in_header { PskMode::Dbpsk } else { self.mode };
+                for i in 1..mode.bits_per_symbol() {
+                    symbol |= self.next_bit(input).unwrap_or(0) << i;
+                }
+                let quadrant = mode.quadrant(symbol);
+                self.symbol_phase += f64::from(quadrant) * f64::consts::PI / 2.0;
+                self.symbol_phase %= 2.0 * f64::consts::PI;
+            }
+            output.push((self.phase + self.symbol_phase).cos());
+            self.phase += self.omega;
+        }
+    }
+
+    /// Unmodulated carrier at the current symbol phase, to pad around a
+    /// burst.  Before a burst it doubles as the phase reference for the
+    /// first symbol.
+    pub fn carrier(&mut self, samples: usize) -> Vec<f64> {
+        let mut output: Vec<f64> = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            output.push((self.phase + self.symbol_phase).cos());
+            self.phase += self.omega;
+        }
+        output
+    }
+
+    // Whether the next bit belongs to the part of the burst that is sent
+    // one bit per symbol
+    fn in_mode_header(&self) -> bool {
+        let byte = if self.bit_pos > 0 { self.data_pos - 1 } else { self.data_pos };
+        byte < MODE_HEADER_LEN
+    }
+
+    // Pull the next bit out of the input, LSB first
+    fn next_bit(&mut self, input: &[u8]) -> Option<u32> {
+        if self.bit_pos == 0 {
+            if self.data_pos < input.len() {
+                self.current_byte = input[self.data_pos];
+                self.data_pos += 1;
+                self.bit_pos = 8;
+            } else {
+                return None;
+            }
+        }
+        let bit = u32::from(self.current_byte & 1);
+        self.current_byte >>= 1;
+        self.bit_pos -= 1;
+        Some(bit)
+    }
+}
+
+/// Recovers bytes from the output of a `PskEncoder`.  It expects to start
+/// in step with a freshly-created encoder, as it would in a loopback test,
+/// and uses the part-symbol before the first symbol boundary as its
+/// phase reference.
+pub struct PskDecoder {
+    mode: PskMode,
+    baud_incr: f64,
+    omega: f64,
+}
+
+impl PskDecoder {
+    pub fn new(mode: PskMode, carrier: f64, baud_rate: f64, sample_rate: f64) -> PskDecoder {
+        PskDecoder {
+            mode,
+            baud_incr: baud_rate / sample_rate,
+            omega: (2.0 * f64::consts::PI * carrier) / sample_rate,
+        }
+    }
+
+    pub fn demodulate(&self, samples: &[f64]) -> Vec<u8> {
+        let mut deltas = vec![];
+        let mut baud_frac = 0.0;
+        let mut reference: Option<(f64, f64)> = None;
+        let mut acc = (0.0, 0.0);
+
+        for (n, sample) in samples.iter().enumerate() {
+            baud_frac += self.baud_incr;
+            if baud_frac >= 1.0 {
+                baud_frac -= 1.0;
+                if let Some(prev) = reference {
+                    deltas.push(phase_change(prev, acc));
+                }
+                reference = Some(acc);
+                acc = (0.0, 0.0);
+            }
+            // Mix down against our own carrier and integrate over the symbol
+            let phase = self.omega * n as f64;
+            acc.0 += sample * phase.cos();
+            acc.1 -= sample * phase.sin();
+        }
+        if let Some(prev) = reference {
+            deltas.push(phase_change(prev, acc));
+        }
+
+        let mut output = vec![];
+        let mut current_byte = 0u8;
+        let mut bit_pos = 0;
+        for delta in deltas {
+            let mode = if output.len() < MODE_HEADER_LEN { PskMode::Dbpsk } else { self.mode };
+            let symbol = mode.symbol(delta);
+            for i in 0..mode.bits_per_symbol() {
+                current_byte |= (((symbol >> i) & 1) as u8) << bit_pos;
+                bit_pos += 1;
+                if bit_pos == 8 {
+                    output.push(current_byte);
+                    current_byte = 0;
+                    bit_pos = 0;
+                }
+            }
+        }
+        output
+    }
+}
+
+// Angle of `to` relative to `from`, where both are (re, im) pairs
+fn phase_change(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let re = to.0 * from.0 + to.1 * from.1;
+    let im = to.1 * from.0 - to.0 * from.1;
+    im.atan2(re)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loopback(mode: PskMode, baud_rate: f64, sample_rate: f64, input: &[u8]) -> Vec<u8> {
+        let mut encoder = PskEncoder::new(mode, 4000.0, baud_rate, sample_rate);
+        let decoder = PskDecoder::new(mode, 4000.0, baud_rate, sample_rate);
+        decoder.demodulate(&encoder.modulate(input))
+    }
+
+    #[test]
+    fn dbpsk_loopback() {
+        let input: Vec<u8> = (0..=255).collect();
+        assert_eq!(loopback(PskMode::Dbpsk, 1000.0, 48000.0, &input), input);
+    }
+
+    #[test]
+    fn dqpsk_loopback() {
+        let input: Vec<u8> = (0..=255).rev().collect();
+        assert_eq!(loopback(PskMode::Dqpsk, 1000.0, 48000.0, &input), input);
+    }
+
+    // 44100 / 1200 is not a whole number of samples per symbol, so the
+    // symbol boundaries drift against the samples, and odd byte counts
+    // end part-way through the carrier's phase pattern
+    #[test]
+    fn uneven_lengths() {
+        let input = [0x35, 0xac, 0x95];
+        assert_eq!(loopback(PskMode::Dbpsk, 1200.0, 44100.0, &input), input);
+        assert_eq!(loopback(PskMode::Dqpsk, 1200.0, 44100.0, &input), input);
+        assert_eq!(loopback(PskMode::Dqpsk, 1200.0, 44100.0, &input[..1]), &input[..1]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::f64;
+
+// Number of sinc zero crossings kept either side of each output sample
+const ZERO_CROSSINGS: f64 = 16.0;
+
+// Fraction of the lower Nyquist frequency left untouched by the
+// anti-aliasing filter
+const ROLLOFF: f64 = 0.95;
+
+/// Resample `input` from `from_rate` to `to_rate` using band-limited
+/// (windowed sinc) interpolation.  The same input always produces the
+/// same output, so audio modulated at a fixed rate sounds identical on
+/// every playback device.
+pub fn resample(input: &[f64], from_rate: f64, to_rate: f64) -> Vec<f64> {
+    let mut resampler = Resampler::new(from_rate, to_rate);
+    let mut output = resampler.process(input);
+    output.extend(resampler.finish());
+    output
+}
+
+/// Resamples audio that arrives a piece at a time, giving the same
+/// result as `resample()` on the whole of it.  Each output sample is
+/// produced once all the input it depends on has arrived.
+pub struct Resampler {
+    ratio: f64,
+    cutoff: f64,
+    half_width: f64,
+
+    // Input that later output samples still need, and the position of
+    // its first sample in the whole input
+    input: Vec<f64>,
+    offset: usize,
+
+    // Number of the next output sample
+    next: usize,
+}
+
+impl Resampler {
+    pub fn new(from_rate: f64, to_rate: f64) -> Resampler {
+        let ratio = to_rate / from_rate;
+        // When downsampling, lower the cutoff to the new Nyquist frequency
+        let cutoff = ratio.min(1.0) * ROLLOFF;
+        Resampler {
+            ratio,
+            cutoff,
+            half_width: ZERO_CROSSINGS / cutoff,
+            input: vec![],
+            offset: 0,
+            next: 0,
+        }
+    }
+
+    /// Resample the next piece of the input.  Up to `ZERO_CROSSINGS`
+    /// samples' worth of output is held back until more input arrives.
+    pub fn process(&mut self, input: &[f64]) -> Vec<f64> {
+        if self.ratio == 1.0 {
+            return input.to_vec();
+        }
+        self.input.extend_from_slice(input);
+        self.run(false)
+    }
+
+    /// Resample whatever is still held back, treating the input as ended
+    pub fn finish(&mut self) -> Vec<f64> {
+        if self.ratio == 1.0 {
+            return vec![];
+        }
+        self.run(true)
+    }
+
+    fn run(&mut self, ended: bool) -> Vec<f64> {
+        let received = self.offset + self.input.len();
+        let output_len = (received as f64 * self.ratio).ceil() as usize;
+
+        let mut output = vec![];
+        while self.next < output_len {
+            let t = self.next as f64 / self.ratio;
+            let first = ((t - self.half_width).ceil() as isize).max(0);
+            let last = (t + self.half_width).floor() as isize;
+            if !ended && last >= received as isize {
+                break;
+            }
+            let last = last.min(received as isize - 1);
+
+            let mut sum = 0.0;
+            for i in first..=last {
+                let x = t - i as f64;
+                sum += self.input[i as usize - self.offset] * self.cutoff * sinc(self.cutoff * x) * blackman(x / self.half_width);
+            }
+            output.push(sum);
+            self.next += 1;
+        }
+
+        // Let go of input that no later output sample reaches
+        let t = self.next as f64 / self.ratio;
+        let needed = ((t - self.half_width).ceil() as isize).max(0) as usize;
+        let unused = needed.saturating_sub(self.offset).min(self.input.len());
+        self.input.drain(..unused);
+        self.offset += unused;
+        output
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let px = f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+// Blackman window over -1 .. 1
+fn blackman(x: f64) -> f64 {
+    let phase = f64::consts::PI * (x + 1.0);
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(len: usize) -> Vec<f64> {
+        (0..len).map(|n| (n as f64 * 0.3).sin()).collect()
+    }
+
+    #[test]
+    fn pieces_match_whole() {
+        let input = tone(3000);
+        for &(from, to) in &[(48000.0, 44100.0), (44100.0, 48000.0), (48000.0, 96000.0)] {
+            let whole = resample(&input, from, to);
+            assert_eq!(whole.len(), (input.len() as f64 * to / from).ceil() as usize);
+            for &size in &[1, 37, 1000] {
+                let mut resampler = Resampler::new(from, to);
+                let mut output = vec![];
+                for piece in input.chunks(size) {
+                    output.extend(resampler.process(piece));
+                }
+                output.extend(resampler.finish());
+                assert_eq!(output, whole);
+            }
+        }
+    }
+
+    #[test]
+    fn same_rate() {
+        let input = tone(100);
+        assert_eq!(resample(&input, 48000.0, 48000.0), input);
+    }
+
+    #[test]
+    fn keeps_little_input() {
+        let mut resampler = Resampler::new(48000.0, 44100.0);
+        for piece in tone(48000).chunks(480) {
+            resampler.process(piece);
+            assert!(resampler.input.len() < 480 + 2 * (resampler.half_width as usize + 1));
+        }
+    }
+}
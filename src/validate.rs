@@ -0,0 +1,366 @@
+use std;
+
+use ofdm::OfdmParams;
+
+// Fewest samples per symbol that the encoders can produce at all
+const MIN_SAMPLES_PER_SYMBOL: f64 = 1.0;
+
+// Below this, symbols are too short for a receiver to resolve reliably
+const LOW_SAMPLES_PER_SYMBOL: f64 = 4.0;
+
+// Tones above this fraction of the sample rate come out badly aliased
+const HIGH_TONE_FRACTION: f64 = 0.4;
+
+// How far the modulation index may stray from a multiple of 0.5
+const ORTHOGONAL_TOLERANCE: f64 = 0.05;
+
+// Tones that overlap less than this over one symbol are far enough apart
+// for a non-coherent receiver, even if they aren't quite orthogonal.
+// Bell 202 (h = 0.833) comes in at 0.19.
+const MAX_TONE_OVERLAP: f64 = 0.25;
+
+/// Most tones FSK can use.  The mode code in the version byte is the bits
+/// per symbol less one, and codes from 8 up mean PSK or OFDM.
+pub const MAX_FSK_TONES: usize = 128;
+
+/// Settings that cannot produce a usable signal
+#[derive(Debug)]
+pub enum ValidationError {
+    NotPositive(&'static str, f64),
+    AboveNyquist { tone: f64, nyquist: f64 },
+    DuplicateTone(f64),
+    TooManyTones(usize),
+    TooFewSamples { baud_rate: f64, sample_rate: f64 },
+    FftSizeNotPowerOfTwo(usize),
+    CyclicPrefixTooLong { cyclic_prefix: usize, fft_size: usize },
+    NoDataSubcarriers,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ValidationError::NotPositive(name, value) => {
+                write!(f, "{} must be greater than zero, not {}", name, value)
+            }
+            ValidationError::AboveNyquist { tone, nyquist } => write!(
+                f,
+                "tone of {} Hz is at or above the Nyquist frequency of {} Hz",
+                tone, nyquist
+            ),
+            ValidationError::DuplicateTone(tone) => write!(f, "tone of {} Hz is used more than once", tone),
+            ValidationError::TooManyTones(count) => write!(
+                f,
+                "{} tones is more than the {} that FSK can signal in the version byte",
+                count, MAX_FSK_TONES
+            ),
+            ValidationError::TooFewSamples { baud_rate, sample_rate } => write!(
+                f,
+                "baud rate of {} is faster than the sample rate of {}",
+                baud_rate, sample_rate
+            ),
+            ValidationError::FftSizeNotPowerOfTwo(size) => {
+                write!(f, "OFDM FFT size of {} is not a power of two", size)
+            }
+            ValidationError::CyclicPrefixTooLong { cyclic_prefix, fft_size } => write!(
+                f,
+                "OFDM cyclic prefix of {} samples is longer than the FFT size of {}",
+                cyclic_prefix, fft_size
+            ),
+            ValidationError::NoDataSubcarriers => {
+                write!(f, "every OFDM subcarrier is a pilot, leaving none for data")
+            }
+        }
+    }
+}
+
+/// Settings that will work, but probably not well
+#[derive(Debug)]
+pub enum ValidationWarning {
+    FewSamplesPerSymbol(f64),
+    NearNyquist { tone: f64, sample_rate: f64 },
+    TonesSwapped { f_lo: f64, f_hi: f64 },
+    ModulationIndex(f64),
+    NotOrthogonal(f64),
+}
+
+impl ValidationWarning {
+    /// Whether the warning is about the choice of tones and baud rate,
+    /// which a standard preset has already settled
+    pub fn is_about_tones(&self) -> bool {
+        match *self {
+            ValidationWarning::TonesSwapped { .. }
+            | ValidationWarning::ModulationIndex(_)
+            | ValidationWarning::NotOrthogonal(_) => true,
+            ValidationWarning::FewSamplesPerSymbol(_) | ValidationWarning::NearNyquist { .. } => false,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ValidationWarning::FewSamplesPerSymbol(samples) => write!(
+                f,
+                "only {:.2} samples per symbol, which receivers may struggle to resolve",
+                samples
+            ),
+            ValidationWarning::NearNyquist { tone, sample_rate } => write!(
+                f,
+                "tone of {} Hz is close to the Nyquist frequency at {} Hz and will be distorted",
+                tone, sample_rate
+            ),
+            ValidationWarning::TonesSwapped { f_lo, f_hi } => write!(
+                f,
+                "F_LO ({} Hz) is above F_HI ({} Hz), so 0 bits use the higher tone (expected for Bell 202)",
+                f_lo, f_hi
+            ),
+            ValidationWarning::ModulationIndex(h) => write!(
+                f,
+                "modulation index of {:.3} is far from 1, so tones are {}",
+                h,
+                if h < 1.0 { "hard to tell apart" } else { "wasting bandwidth" }
+            ),
+            ValidationWarning::NotOrthogonal(h) => write!(
+                f,
+                "modulation index of {:.3} is not a multiple of 0.5, so the tones are not orthogonal",
+                h
+            ),
+        }
+    }
+}
+
+fn check_positive(name: &'static str, value: f64) -> Result<(), ValidationError> {
+    if value > 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(ValidationError::NotPositive(name, value))
+    }
+}
+
+fn check_tone(tone: f64, sample_rate: f64, warnings: &mut Vec<ValidationWarning>) -> Result<(), ValidationError> {
+    check_positive("Tone frequency", tone)?;
+    let nyquist = sample_rate / 2.0;
+    if tone >= nyquist {
+        return Err(ValidationError::AboveNyquist { tone, nyquist });
+    }
+    if tone > sample_rate * HIGH_TONE_FRACTION {
+        warnings.push(ValidationWarning::NearNyquist { tone, sample_rate });
+    }
+    Ok(())
+}
+
+fn check_symbol_rate(
+    baud_rate: f64,
+    sample_rate: f64,
+    warnings: &mut Vec<ValidationWarning>,
+) -> Result<(), ValidationError> {
+    check_positive("Sample rate", sample_rate)?;
+    check_positive("Baud rate", baud_rate)?;
+    let samples_per_symbol = sample_rate / baud_rate;
+    if samples_per_symbol < MIN_SAMPLES_PER_SYMBOL {
+        return Err(ValidationError::TooFewSamples { baud_rate, sample_rate });
+    }
+    if samples_per_symbol < LOW_SAMPLES_PER_SYMBOL {
+        warnings.push(ValidationWarning::FewSamplesPerSymbol(samples_per_symbol));
+    }
+    Ok(())
+}
+
+/// Check FSK settings, where `tones` is `[f_lo, f_hi]` for binary FSK
+/// or the full tone list for M-FSK.
+pub fn check_fsk(sample_rate: f64, baud_rate: f64, tones: &[f64]) -> Result<Vec<ValidationWarning>, ValidationError> {
+    if tones.len() > MAX_FSK_TONES {
+        return Err(ValidationError::TooManyTones(tones.len()));
+    }
+    let mut warnings = vec![];
+    check_symbol_rate(baud_rate, sample_rate, &mut warnings)?;
+    for tone in tones {
+        check_tone(*tone, sample_rate, &mut warnings)?;
+    }
+
+    let mut sorted = tones.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    for pair in sorted.windows(2) {
+        if pair[0] == pair[1] {
+            return Err(ValidationError::DuplicateTone(pair[0]));
+        }
+    }
+    if tones.len() == 2 && tones[0] > tones[1] {
+        warnings.push(ValidationWarning::TonesSwapped { f_lo: tones[0], f_hi: tones[1] });
+    }
+
+    // Look at the closest pair of tones, since they are the hardest to
+    // tell apart.  An index of 0.5 (MSK) is the closest spacing that is
+    // still orthogonal.
+    let spacing = sorted
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .fold(f64::INFINITY, f64::min);
+    let h = spacing / baud_rate;
+    if !(0.5 - ORTHOGONAL_TOLERANCE..=2.5).contains(&h) {
+        warnings.push(ValidationWarning::ModulationIndex(h));
+    }
+    // Only worry about orthogonality when the tones are close enough for
+    // their overlap to matter
+    let overlap = ((std::f64::consts::PI * h).sin() / (std::f64::consts::PI * h)).abs();
+    if ((h * 2.0).round() - h * 2.0).abs() > ORTHOGONAL_TOLERANCE * 2.0 && overlap > MAX_TONE_OVERLAP {
+        warnings.push(ValidationWarning::NotOrthogonal(h));
+    }
+    Ok(warnings)
+}
+
+/// Check PSK settings
+pub fn check_carrier(sample_rate: f64, baud_rate: f64, carrier: f64) -> Result<Vec<ValidationWarning>, ValidationError> {
+    let mut warnings = vec![];
+    check_symbol_rate(baud_rate, sample_rate, &mut warnings)?;
+    check_tone(carrier, sample_rate, &mut warnings)?;
+    Ok(warnings)
+}
+
+/// Check OFDM settings, with the subcarriers already laid out for the
+/// sample rate
+pub fn check_ofdm(sample_rate: f64, params: &OfdmParams) -> Result<Vec<ValidationWarning>, ValidationError> {
+    let mut warnings = vec![];
+    check_positive("Sample rate", sample_rate)?;
+    if !params.fft_size.is_power_of_two() {
+        return Err(ValidationError::FftSizeNotPowerOfTwo(params.fft_size));
+    }
+    if params.cyclic_prefix > params.fft_size {
+        return Err(ValidationError::CyclicPrefixTooLong {
+            cyclic_prefix: params.cyclic_prefix,
+            fft_size: params.fft_size,
+        });
+    }
+    check_positive("OFDM pilot spacing", params.pilot_spacing as f64)?;
+    check_positive("OFDM subcarrier count", params.subcarriers as f64)?;
+    if params.bits_per_symbol() == 0 {
+        return Err(ValidationError::NoDataSubcarriers);
+    }
+    check_tone(params.top_frequency(sample_rate), sample_rate, &mut warnings)?;
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn above_nyquist() {
+        match check_fsk(44100.0, 1200.0, &[8666.0, 30000.0]) {
+            Err(ValidationError::AboveNyquist { tone, nyquist }) => {
+                assert_eq!(tone, 30000.0);
+                assert_eq!(nyquist, 22050.0);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Nyquist itself is no good either
+        match check_carrier(44100.0, 1200.0, 22050.0) {
+            Err(ValidationError::AboveNyquist { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_tone() {
+        match check_fsk(48000.0, 1000.0, &[3000.0, 4000.0, 3000.0, 5000.0]) {
+            Err(ValidationError::DuplicateTone(tone)) => assert_eq!(tone, 3000.0),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn too_many_tones() {
+        let tones = |count: usize| (0..count).map(|i| 1000.0 + 50.0 * i as f64).collect::<Vec<f64>>();
+        assert!(check_fsk(96000.0, 50.0, &tones(MAX_FSK_TONES)).is_ok());
+        match check_fsk(96000.0, 50.0, &tones(2 * MAX_FSK_TONES)) {
+            Err(ValidationError::TooManyTones(256)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn too_few_samples() {
+        match check_fsk(8000.0, 9000.0, &[1000.0, 2000.0]) {
+            Err(ValidationError::TooFewSamples { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match check_fsk(48000.0, 0.0, &[1000.0, 2000.0]) {
+            Err(ValidationError::NotPositive("Baud rate", _)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        // One sample per symbol is the least that can be generated
+        let warnings = check_carrier(8000.0, 8000.0, 1000.0).unwrap();
+        match warnings[..] {
+            [ValidationWarning::FewSamplesPerSymbol(samples)] => assert_eq!(samples, 1.0),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn few_samples_threshold() {
+        assert!(check_carrier(48000.0, 12000.0, 6000.0).unwrap().is_empty());
+        match check_carrier(48000.0, 12500.0, 6000.0).unwrap()[..] {
+            [ValidationWarning::FewSamplesPerSymbol(samples)] => assert_eq!(samples, 3.84),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn near_nyquist_threshold() {
+        assert!(check_carrier(44100.0, 1200.0, 17640.0).unwrap().is_empty());
+        match check_carrier(44100.0, 1200.0, 17641.0).unwrap()[..] {
+            [ValidationWarning::NearNyquist { tone, .. }] => assert_eq!(tone, 17641.0),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn modulation_index() {
+        // Bell 202 is close enough to orthogonal to pass quietly
+        let warnings = check_fsk(48000.0, 1200.0, &[1200.0, 2200.0]).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        match check_fsk(48000.0, 1000.0, &[1000.0, 1200.0]).unwrap()[..] {
+            [ValidationWarning::ModulationIndex(h), ValidationWarning::NotOrthogonal(_)] => {
+                assert!((h - 0.2).abs() < 1e-9)
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        match check_fsk(48000.0, 1000.0, &[2000.0, 1000.0]).unwrap()[..] {
+            [ValidationWarning::TonesSwapped { .. }] => (),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn ofdm(fft_size: usize, subcarriers: usize, cyclic_prefix: usize, pilot_spacing: usize) -> OfdmParams {
+        OfdmParams::centred(48000.0, 12000.0, fft_size, subcarriers, cyclic_prefix, pilot_spacing)
+    }
+
+    #[test]
+    fn ofdm_defaults() {
+        assert!(check_ofdm(48000.0, &ofdm(256, 48, 32, 8)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ofdm_errors() {
+        match check_ofdm(48000.0, &ofdm(256, 48, 9999, 8)) {
+            Err(ValidationError::CyclicPrefixTooLong { cyclic_prefix: 9999, fft_size: 256 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match check_ofdm(48000.0, &ofdm(256, 48, 64, 0)) {
+            Err(ValidationError::NotPositive(_, _)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match check_ofdm(48000.0, &ofdm(256, 200, 64, 8)) {
+            Err(ValidationError::AboveNyquist { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match check_ofdm(48000.0, &ofdm(250, 48, 64, 8)) {
+            Err(ValidationError::FftSizeNotPowerOfTwo(250)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match check_ofdm(48000.0, &ofdm(256, 48, 64, 1)) {
+            Err(ValidationError::NoDataSubcarriers) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! JavaScript bindings, built with the `wasm` feature:
+//!
+//! ```text
+//! cargo rustc --release --lib --target wasm32-unknown-unknown --no-default-features --features wasm --crate-type cdylib
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/ltc_modulate.wasm
+//! ```
+//!
+//! From JavaScript, set up an `Encoder` and hand it a program:
+//!
+//! ```js
+//! const encoder = new Encoder();
+//! encoder.setEncodingRate("mid");
+//! const samples = encoder.encode(program);    // Float32Array
+//! const wav = encoder.encodeWav(program);     // Uint8Array
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use controller;
+use encoder;
+use preset;
+use validate;
+use wav;
+use EncodingRate;
+
+/// Upload settings, starting out the same as the command-line defaults
+#[wasm_bindgen]
+pub struct Encoder {
+    inner: encoder::Encoder,
+}
+
+#[wasm_bindgen]
+impl Encoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Encoder {
+        Encoder {
+            inner: encoder::Encoder::new(),
+        }
+    }
+
+    /// Take the baud rate, tones and framing from a named modem preset
+    #[wasm_bindgen(js_name = setPreset)]
+    pub fn set_preset(&mut self, name: &str) -> Result<(), JsValue> {
+        match preset::find(name) {
+            Some(p) => self.inner.use_preset(p),
+            None => return Err(JsValue::from_str(&format!("Unrecognized preset: {}", name))),
+        };
+        Ok(())
+    }
+
+    /// Data protocol version, 1 or 2
+    #[wasm_bindgen(js_name = setProtocolVersion)]
+    pub fn set_protocol_version(&mut self, version: u8) -> Result<(), JsValue> {
+        self.inner.protocol_version = match version {
+            1 => controller::ProtocolVersion::V1,
+            2 => controller::ProtocolVersion::V2,
+            x => return Err(JsValue::from_str(&format!("Unrecognized version found: {}", x))),
+        };
+        Ok(())
+    }
+
+    /// "high", "mid", "low", or a whole-number factor to slow the
+    /// transmission by
+    #[wasm_bindgen(js_name = setEncodingRate)]
+    pub fn set_encoding_rate(&mut self, rate: &str) -> Result<(), JsValue> {
+        self.inner.rate = match EncodingRate::parse(rate) {
+            Some(rate) => rate,
+            None => return Err(JsValue::from_str(&format!("Unrecognized rate found: {}", rate))),
+        };
+        Ok(())
+    }
+
+    /// Generate an OS update rather than a program upload
+    #[wasm_bindgen(js_name = setOsUpdate)]
+    pub fn set_os_update(&mut self, os_update: bool) {
+        self.inner.os_update = os_update;
+    }
+
+    #[wasm_bindgen(js_name = setBaudRate)]
+    pub fn set_baud_rate(&mut self, baud_rate: f64) {
+        self.inner.baud_rate = baud_rate;
+    }
+
+    /// FSK tones, lowest symbol first.  There must be 2, 4, 8... of them.
+    #[wasm_bindgen(js_name = setTones)]
+    pub fn set_tones(&mut self, tones: &[f64]) -> Result<(), JsValue> {
+        if tones.len() < 2 || !tones.len().is_power_of_two() || tones.len() > validate::MAX_FSK_TONES {
+            return Err(JsValue::from_str(&format!(
+                "Number of tones must be a power of two from 2 to {}, but {} were given",
+                validate::MAX_FSK_TONES,
+                tones.len()
+            )));
+        }
+        self.inner.tones = tones.to_vec();
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = setRepeatCount)]
+    pub fn set_repeat_count(&mut self, repeat_count: u32) {
+        self.inner.repeat_count = repeat_count;
+    }
+
+    #[wasm_bindgen(js_name = setSampleRate)]
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.inner.sample_rate = sample_rate;
+    }
+
+    /// Modulate a program into samples in the range -1.0 .. 1.0
+    pub fn encode(&self, program: &[u8]) -> Result<Vec<f32>, JsValue> {
+        Ok(self.transmit(program)?.iter().map(|s| *s as f32).collect())
+    }
+
+    /// Modulate a program into the bytes of a 16-bit WAV file
+    #[wasm_bindgen(js_name = encodeWav)]
+    pub fn encode_wav(&self, program: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let samples = self.transmit(program)?;
+        let mut output = vec![];
+        wav::write_wav_to(self.inner.sample_rate as u32, &samples, &wav::WavOptions::default(), &mut output)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(output)
+    }
+
+    /// Roughly how long a program of `length` bytes takes to send, in seconds
+    #[wasm_bindgen(js_name = estimatedDuration)]
+    pub fn estimated_duration(&self, length: usize) -> f64 {
+        self.inner.estimated_duration(length)
+    }
+
+    fn transmit(&self, program: &[u8]) -> Result<Vec<f64>, JsValue> {
+        self.inner
+            .transmit(program)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
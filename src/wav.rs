@@ -1,58 +1,914 @@
-extern crate byteorder;
-use std;
-use std::io::prelude::*;
-use std::fs::File;
-use self::byteorder::{LittleEndian, WriteBytesExt};
-
-const FORMAT_PCM  : u16 = 1;
-
-pub fn write_wav(rate: u32, samples: &[i16], filename: &str) -> std::io::Result<()> {
-    let mut file = File::create(filename)?;
-    let bits_per_sample = 16;
-    let num_channels = 1;
-    /* chunkId */       file.write_all(&[0x52, 0x49, 0x46, 0x46])?;        // 'RIFF'
-    /* chunkSize */     file.write_u32::<LittleEndian>(36 + (samples.len() as u32 * (bits_per_sample / 8)))?;
-    /* format */        file.write_all(&[0x57, 0x41, 0x56, 0x45])?;        // 'WAVE'
-    /* subChunk1Id */   file.write_all(&[0x66, 0x6d, 0x74, 0x20])?;        // 'fmt '
-    /* subChunk1Size */ file.write_u32::<LittleEndian>(16 as u32)?;             // 16 bytes for PCM
-    /* audioFormat */   file.write_u16::<LittleEndian>(FORMAT_PCM)?;            // 1 = PCM
-    /* numChannels */   file.write_u16::<LittleEndian>(num_channels as u16)?;   // 1 = Mono
-    /* sampleRate */    file.write_u32::<LittleEndian>(rate)?;                  // Probably 44100
-    /* byteRate */      file.write_u32::<LittleEndian>(rate * num_channels * (bits_per_sample / 8) as u32)?;
-    /* blockAlign */    file.write_u16::<LittleEndian>(num_channels as u16 * (bits_per_sample / 8) as u16)?;
-    /* bitsPerSample */ file.write_u16::<LittleEndian>(bits_per_sample as u16)?;
-    /* subChunk2Id */   file.write_all(&[0x64, 0x61, 0x74, 0x61])?;        // 'data'
-    /* subChunk2Size */ file.write_u32::<LittleEndian>(samples.len() as u32 * (bits_per_sample / 8))?;
-    if cfg!(target_endian = "big") {
-        for sample in samples {
-            file.write_i16::<LittleEndian>(*sample)?;
-        }
-    } else {
-        use std::{slice, mem};
-        let slice_u8: &[u8] = unsafe {
-            slice::from_raw_parts(
-                samples.as_ptr() as *const u8,
-                samples.len() * mem::size_of::<u16>()
-            )
-        };
-        file.write_all(slice_u8)?;
-    }
-
-    Ok(())
-}
-
-/*
-      chunkId      : [0x52,0x49,0x46,0x46], // 0    4    "RIFF" = 0x52494646
-      chunkSize    : 0,                     // 4    4    36+SubChunk2Size = 4+(8+SubChunk1Size)+(8+SubChunk2Size)
-      format       : [0x57,0x41,0x56,0x45], // 8    4    "WAVE" = 0x57415645
-      subChunk1Id  : [0x66,0x6d,0x74,0x20], // 12   4    "fmt " = 0x666d7420
-      subChunk1Size: 16,                    // 16   4    16 for PCM
-      audioFormat  : 1,                     // 20   2    PCM = 1
-      numChannels  : options.channels,      // 22   2    Mono = 1, Stereo = 2...
-      sampleRate   : options.rate,          // 24   4    8000, 44100...
-      byteRate     : 0,                     // 28   4    SampleRate*NumChannels*BitsPerSample/8
-      blockAlign   : 0,                     // 32   2    NumChannels*BitsPerSample/8
-      bitsPerSample: options.depth,                     // 34   2    8 bits = 8, 16 bits = 16
-      subChunk2Id  : [0x64,0x61,0x74,0x61], // 36   4    "data" = 0x64617461
-      subChunk2Size: 0                      // 40   4    data size = NumSamples*NumChannels*BitsPerSample/8
-*/
+extern crate byteorder;
+use std;
+use std::io::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, SeekFrom};
+use self::byteorder::{LittleEndian, WriteBytesExt};
+
+const FORMAT_PCM  : u16 = 1;
+const FORMAT_IEEE_FLOAT : u16 = 3;
+const FORMAT_EXTENSIBLE : u16 = 0xfffe;
+
+// The rest of the KSDATAFORMAT_SUBTYPE GUID, after the format tag
+const SUBTYPE_GUID_TAIL: [u8; 12] = [0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71];
+
+// Chunk size written when the length isn't known yet, as when piping,
+// or when the real size is in the RF64 `ds64` chunk
+const STREAMING_LEN: u32 = 0xffff_ffff;
+
+// Size of a `ds64` chunk with no table
+const DS64_LEN: u32 = 28;
+
+// Speaker masks for one and two channels
+const SPEAKER_FRONT_CENTER: u32 = 0x4;
+const SPEAKER_FRONT_LEFT_RIGHT: u32 = 0x3;
+
+/// How each sample is stored in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    /// 8-bit unsigned PCM, centred on 128
+    U8,
+
+    /// 16-bit signed PCM
+    S16,
+
+    /// 24-bit signed PCM
+    S24,
+
+    /// 32-bit signed PCM
+    S32,
+
+    /// 32-bit IEEE float, with no quantization
+    F32,
+}
+
+/// Names accepted by `SampleFormat::parse`
+pub const SAMPLE_FORMAT_NAMES: [&str; 5] = ["u8", "s16", "s24", "s32", "f32"];
+
+impl SampleFormat {
+    pub fn parse(s: &str) -> Option<SampleFormat> {
+        match s {
+            "u8" => Some(SampleFormat::U8),
+            "s16" => Some(SampleFormat::S16),
+            "s24" => Some(SampleFormat::S24),
+            "s32" => Some(SampleFormat::S32),
+            "f32" => Some(SampleFormat::F32),
+            _ => None,
+        }
+    }
+
+    pub fn bits_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::U8 => 8,
+            SampleFormat::S16 => 16,
+            SampleFormat::S24 => 24,
+            SampleFormat::S32 | SampleFormat::F32 => 32,
+        }
+    }
+
+    pub fn bytes_per_sample(self) -> u32 {
+        u32::from(self.bits_per_sample() / 8)
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            SampleFormat::F32 => FORMAT_IEEE_FLOAT,
+            _ => FORMAT_PCM,
+        }
+    }
+
+    /// The integer a sample in the range -1.0 .. 1.0 is stored as, or
+    /// `None` for float samples
+    pub fn quantize(self, sample: f64) -> Option<i32> {
+        match self {
+            SampleFormat::U8 => Some((sample * 127.0).round().clamp(-128.0, 127.0) as i32),
+            SampleFormat::S16 => Some(i32::from((sample * 32767.0).round() as i16)),
+            SampleFormat::S24 => Some((sample * 8_388_607.0).round().clamp(-8_388_608.0, 8_388_607.0) as i32),
+            SampleFormat::S32 => Some((sample * 2_147_483_647.0).round() as i32),
+            SampleFormat::F32 => None,
+        }
+    }
+
+    /// Append a sample in the range -1.0 .. 1.0, little-endian
+    pub fn encode(self, sample: f64, output: &mut Vec<u8>) {
+        match (self, self.quantize(sample)) {
+            (SampleFormat::U8, Some(value)) => output.push((value + 128) as u8),
+            (SampleFormat::S16, Some(value)) => output.extend_from_slice(&(value as i16).to_le_bytes()),
+            (SampleFormat::S24, Some(value)) => output.extend_from_slice(&value.to_le_bytes()[..3]),
+            (SampleFormat::S32, Some(value)) => output.extend_from_slice(&value.to_le_bytes()),
+            _ => output.extend_from_slice(&(sample as f32).to_le_bytes()),
+        }
+    }
+
+    /// Append a sample big-endian, the way AIFF and .au files store them.
+    /// Their 8-bit samples are signed.
+    pub fn encode_be(self, sample: f64, output: &mut Vec<u8>) {
+        match self.quantize(sample) {
+            Some(value) => output.extend_from_slice(&value.to_be_bytes()[4 - self.bytes_per_sample() as usize..]),
+            None => output.extend_from_slice(&(sample as f32).to_be_bytes()),
+        }
+    }
+}
+
+/// How the signal is spread across the output channels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelLayout {
+    /// A single channel
+    Mono,
+
+    /// Data on the left, with the right channel silent
+    Left,
+
+    /// The same data on both channels
+    Both,
+
+    /// Data on the left and an inverted copy on the right, for driving
+    /// a sticker across both channels
+    Differential,
+}
+
+/// Names accepted by `ChannelLayout::parse`
+pub const CHANNEL_LAYOUT_NAMES: [&str; 4] = ["mono", "left", "both", "differential"];
+
+impl ChannelLayout {
+    pub fn parse(s: &str) -> Option<ChannelLayout> {
+        match s {
+            "mono" => Some(ChannelLayout::Mono),
+            "left" => Some(ChannelLayout::Left),
+            "both" => Some(ChannelLayout::Both),
+            "differential" => Some(ChannelLayout::Differential),
+            _ => None,
+        }
+    }
+
+    pub fn channels(self) -> u16 {
+        match self {
+            ChannelLayout::Mono => 1,
+            _ => 2,
+        }
+    }
+
+    /// The value to send on `channel` for a given sample.  Channels past
+    /// the second, as on a surround sound device, are left silent unless
+    /// the signal goes on every channel.
+    pub fn channel_value(self, sample: f64, channel: usize) -> f64 {
+        match (self, channel) {
+            (ChannelLayout::Mono, _) | (ChannelLayout::Both, _) => sample,
+            (ChannelLayout::Left, 0) | (ChannelLayout::Differential, 0) => sample,
+            (ChannelLayout::Differential, 1) => -sample,
+            _ => 0.0,
+        }
+    }
+}
+
+/// A labelled point or region in the file, in sample frames
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub position: u32,
+
+    /// Length of the region, or 0 for a single point
+    pub length: u32,
+
+    pub label: String,
+}
+
+/// Settings for the file written by `write_wav`
+#[derive(Clone, Debug)]
+pub struct WavOptions {
+    pub format: SampleFormat,
+
+    pub layout: ChannelLayout,
+
+    /// Use a WAVE_FORMAT_EXTENSIBLE header, which some software
+    /// requires for anything other than 8 or 16-bit PCM
+    pub extensible: bool,
+
+    /// Points to mark in `cue ` and `LIST/adtl` chunks
+    pub cues: Vec<Cue>,
+
+    /// Text for the `LIST/INFO` chunk, keyed by tag such as `INAM`
+    pub info: Vec<([u8; 4], String)>,
+
+    /// Write the RIFF and data sizes as 0xffffffff, as a stream that is
+    /// read until it ends, so nothing follows the samples.  INFO text goes
+    /// before the samples instead, and cue points, which aren't known
+    /// until the end, are left out.
+    pub streaming: bool,
+
+    /// Let the file grow past 4 GiB by becoming RF64.  Room is kept for
+    /// the `ds64` chunk, as a `JUNK` chunk that smaller files keep.
+    pub rf64: bool,
+}
+
+impl Default for WavOptions {
+    fn default() -> WavOptions {
+        WavOptions {
+            format: SampleFormat::S16,
+            layout: ChannelLayout::Mono,
+            extensible: false,
+            cues: vec![],
+            info: vec![],
+            streaming: false,
+            rf64: false,
+        }
+    }
+}
+
+pub fn write_wav(rate: u32, samples: &[f64], options: &WavOptions, filename: &str) -> std::io::Result<()> {
+    let mut writer = WavWriter::create(filename, rate, options)?;
+    writer.write_samples(samples)?;
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Write the samples with no header at all, in the format and channel
+/// layout from `options`
+pub fn write_raw(samples: &[f64], options: &WavOptions, filename: &str) -> std::io::Result<()> {
+    let mut file = File::create(filename)?;
+    write_raw_to(samples, options, &mut file)
+}
+
+pub fn write_raw_to<W: Write>(samples: &[f64], options: &WavOptions, file: &mut W) -> std::io::Result<()> {
+    file.write_all(&encode_samples(samples, options))
+}
+
+// Interleave and encode every channel of every sample
+fn encode_samples(samples: &[f64], options: &WavOptions) -> Vec<u8> {
+    let num_channels = usize::from(options.layout.channels());
+    let mut data = Vec::with_capacity(samples.len() * num_channels * options.format.bytes_per_sample() as usize);
+    for sample in samples {
+        for channel in 0..num_channels {
+            options.format.encode(options.layout.channel_value(*sample, channel), &mut data);
+        }
+    }
+    data
+}
+
+/// Write a WAV file to anything that implements `Write`, such as a
+/// `Vec<u8>` when the file is to be kept in memory.  Samples are in the
+/// range -1.0 .. 1.0.
+pub fn write_wav_to<W: Write>(rate: u32, samples: &[f64], options: &WavOptions, file: &mut W) -> std::io::Result<()> {
+    let data = encode_samples(samples, options);
+    let trailer = make_trailer(options)?;
+    write_header(file, rate, options, data.len() as u64, samples.len() as u64, trailer.len() as u64)?;
+    file.write_all(&data)?;
+    if pad_len(options, data.len() as u64) != 0 {
+        file.write_all(&[0])?;
+    }
+    file.write_all(&trailer)?;
+
+    Ok(())
+}
+
+/// Writes a WAV file a piece at a time, for recordings too long to hold
+/// in memory.  The header is written with empty sizes, and `finalize`
+/// goes back and fills them in, switching to RF64 if the file has grown
+/// past 4 GiB.  A stream written with `streaming` set never needs its
+/// header filled in, so it can go to something that can't seek.
+pub struct WavWriter<W: Write> {
+    file: W,
+    rate: u32,
+    options: WavOptions,
+    data_len: u64,
+    frames: u64,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(filename: &str, rate: u32, options: &WavOptions) -> std::io::Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(filename)?), rate, options)
+    }
+}
+
+impl<W: Write> WavWriter<W> {
+    pub fn new(mut file: W, rate: u32, options: &WavOptions) -> std::io::Result<WavWriter<W>> {
+        write_header(&mut file, rate, options, 0, 0, 0)?;
+        Ok(WavWriter {
+            file,
+            rate,
+            options: options.clone(),
+            data_len: 0,
+            frames: 0,
+        })
+    }
+
+    /// Append samples in the range -1.0 .. 1.0.  Nothing is written if the
+    /// file would pass the 4 GiB limit of a RIFF file and `rf64` is off.
+    pub fn write_samples(&mut self, samples: &[f64]) -> std::io::Result<()> {
+        let data = encode_samples(samples, &self.options);
+        let data_len = self.data_len + data.len() as u64;
+        if !self.options.rf64 && !self.options.streaming && riff_len(&self.options, data_len, 0) > u64::from(u32::MAX) {
+            return Err(too_large());
+        }
+        self.file.write_all(&data)?;
+        self.data_len = data_len;
+        self.frames += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Replace the cue points, which often aren't known until every
+    /// sample has been written
+    pub fn set_cues(&mut self, cues: Vec<Cue>) {
+        self.options.cues = cues;
+    }
+
+    /// Finish a stream written with `streaming` set, which has nothing
+    /// after the samples, and hand back the underlying writer
+    pub fn finish_stream(mut self) -> std::io::Result<W> {
+        if !self.options.streaming {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "only a streaming WAV file can be finished without filling in its header",
+            ));
+        }
+        self.file.flush()?;
+        Ok(self.file)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Write the cue points and INFO text, fill in the header and hand
+    /// back the underlying writer
+    pub fn finalize(mut self) -> std::io::Result<W> {
+        let trailer = make_trailer(&self.options)?;
+        if !self.options.rf64 && riff_len(&self.options, self.data_len, trailer.len() as u64) > u64::from(u32::MAX) {
+            return Err(too_large());
+        }
+        if pad_len(&self.options, self.data_len) != 0 {
+            self.file.write_all(&[0])?;
+        }
+        self.file.write_all(&trailer)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        write_header(
+            &mut self.file,
+            self.rate,
+            &self.options,
+            self.data_len,
+            self.frames,
+            trailer.len() as u64,
+        )?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()?;
+        Ok(self.file)
+    }
+}
+
+fn too_large() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "WAV file would be larger than 4 GiB, which needs RF64",
+    )
+}
+
+// Plain PCM has a 16-byte fmt chunk.  Other formats add a cbSize
+// field, and WAVE_FORMAT_EXTENSIBLE fills in the 22 bytes it counts.
+fn fmt_len(options: &WavOptions) -> u32 {
+    if options.extensible {
+        40
+    } else if options.format.format_tag() == FORMAT_PCM {
+        16
+    } else {
+        18
+    }
+}
+
+// Anything but integer PCM also needs a fact chunk
+fn has_fact(options: &WavOptions) -> bool {
+    options.format.format_tag() != FORMAT_PCM
+}
+
+// Chunks are padded to an even length, unless the data runs to the end
+fn pad_len(options: &WavOptions, data_len: u64) -> u64 {
+    if options.streaming {
+        0
+    } else {
+        data_len & 1
+    }
+}
+
+// Size of everything in the file after the RIFF chunk header
+fn riff_len(options: &WavOptions, data_len: u64, trailer_len: u64) -> u64 {
+    let ds64_len = if options.rf64 { 8 + u64::from(DS64_LEN) } else { 0 };
+    let fact_len = if has_fact(options) { 8 + 4 } else { 0 };
+    4 + ds64_len + (8 + u64::from(fmt_len(options))) + fact_len + (8 + data_len + pad_len(options, data_len)) + trailer_len
+}
+
+// Cue points and INFO text, which follow the samples unless streaming
+fn make_trailer(options: &WavOptions) -> std::io::Result<Vec<u8>> {
+    let mut trailer = vec![];
+    if options.streaming {
+        return Ok(trailer);
+    }
+    if !options.cues.is_empty() {
+        trailer.extend(make_cue_chunks(&options.cues)?);
+    }
+    if !options.info.is_empty() {
+        trailer.extend(make_info_chunk(&options.info)?);
+    }
+    Ok(trailer)
+}
+
+// Write everything that comes before the samples.  A file past 4 GiB
+// becomes RF64, with its sizes in the `ds64` chunk, and a stream carries
+// its INFO text here as nothing can follow the samples.
+fn write_header<W: Write>(
+    file: &mut W,
+    rate: u32,
+    options: &WavOptions,
+    data_len: u64,
+    frames: u64,
+    trailer_len: u64,
+) -> std::io::Result<()> {
+    let format = options.format;
+    let num_channels = options.layout.channels();
+    let bits_per_sample = format.bits_per_sample();
+    let block_align = num_channels * (bits_per_sample / 8);
+    let fmt_len = fmt_len(options);
+    let format_tag = if options.extensible { FORMAT_EXTENSIBLE } else { format.format_tag() };
+
+    let riff_len = riff_len(options, data_len, trailer_len);
+    let large = !options.streaming && riff_len > u64::from(u32::MAX);
+    if large && !options.rf64 {
+        return Err(too_large());
+    }
+    let (riff_id, riff_chunk_len, data_chunk_len, frames_len) = if options.streaming || large {
+        (if large { b"RF64" } else { b"RIFF" }, STREAMING_LEN, STREAMING_LEN, STREAMING_LEN)
+    } else {
+        (b"RIFF", riff_len as u32, data_len as u32, frames as u32)
+    };
+
+    /* chunkId */       file.write_all(riff_id)?;
+    /* chunkSize */     file.write_u32::<LittleEndian>(riff_chunk_len)?;
+    /* format */        file.write_all(&[0x57, 0x41, 0x56, 0x45])?;        // 'WAVE'
+    if options.rf64 {
+        // Room for the ds64 chunk, left as JUNK until it is needed
+        /* ds64Id */    file.write_all(if large { b"ds64" } else { b"JUNK" })?;
+        /* ds64Size */  file.write_u32::<LittleEndian>(DS64_LEN)?;
+        /* riffSize */  file.write_u64::<LittleEndian>(riff_len)?;
+        /* dataSize */  file.write_u64::<LittleEndian>(data_len)?;
+        /* frames */    file.write_u64::<LittleEndian>(frames)?;
+        /* tableLen */  file.write_u32::<LittleEndian>(0)?;
+    }
+    /* subChunk1Id */   file.write_all(&[0x66, 0x6d, 0x74, 0x20])?;        // 'fmt '
+    /* subChunk1Size */ file.write_u32::<LittleEndian>(fmt_len)?;
+    /* audioFormat */   file.write_u16::<LittleEndian>(format_tag)?;            // 1 = PCM
+    /* numChannels */   file.write_u16::<LittleEndian>(num_channels)?;          // 1 = Mono, 2 = Stereo
+    /* sampleRate */    file.write_u32::<LittleEndian>(rate)?;                  // Probably 44100
+    /* byteRate */      file.write_u32::<LittleEndian>(rate * u32::from(block_align))?;
+    /* blockAlign */    file.write_u16::<LittleEndian>(block_align)?;
+    /* bitsPerSample */ file.write_u16::<LittleEndian>(bits_per_sample)?;
+    if fmt_len > 16 {
+        /* cbSize */    file.write_u16::<LittleEndian>((fmt_len - 18) as u16)?;
+    }
+    if options.extensible {
+        /* validBits */ file.write_u16::<LittleEndian>(bits_per_sample)?;
+        /* channelMask */ file.write_u32::<LittleEndian>(if num_channels == 1 {
+                            SPEAKER_FRONT_CENTER
+                        } else {
+                            SPEAKER_FRONT_LEFT_RIGHT
+                        })?;
+        /* subFormat */ file.write_u16::<LittleEndian>(format.format_tag())?;
+                        file.write_u16::<LittleEndian>(0)?;
+                        file.write_all(&SUBTYPE_GUID_TAIL)?;
+    }
+    if has_fact(options) {
+        /* factId */    file.write_all(&[0x66, 0x61, 0x63, 0x74])?;        // 'fact'
+        /* factSize */  file.write_u32::<LittleEndian>(4)?;
+        /* frames */    file.write_u32::<LittleEndian>(frames_len)?;
+    }
+    if options.streaming && !options.info.is_empty() {
+        file.write_all(&make_info_chunk(&options.info)?)?;
+    }
+    /* subChunk2Id */   file.write_all(&[0x64, 0x61, 0x74, 0x61])?;        // 'data'
+    /* subChunk2Size */ file.write_u32::<LittleEndian>(data_chunk_len)?;
+
+    Ok(())
+}
+
+// Build a `LIST/INFO` chunk holding each tag as a NUL-terminated string
+fn make_info_chunk(info: &[([u8; 4], String)]) -> std::io::Result<Vec<u8>> {
+    let mut list = vec![];
+    list.write_all(b"INFO")?;
+    for (id, text) in info {
+        let text_len = text.len() as u32 + 1;
+        list.write_all(id)?;
+        list.write_u32::<LittleEndian>(text_len)?;
+        list.write_all(text.as_bytes())?;
+        list.write_all(&[0])?;
+        if text_len & 1 != 0 {
+            list.write_all(&[0])?;
+        }
+    }
+
+    let mut chunk = vec![];
+    chunk.write_all(b"LIST")?;
+    chunk.write_u32::<LittleEndian>(list.len() as u32)?;
+    chunk.write_all(&list)?;
+    Ok(chunk)
+}
+
+// Build a `cue ` chunk listing every cue point, followed by a `LIST/adtl`
+// chunk giving each one a label, and a length if it is a region
+fn make_cue_chunks(cues: &[Cue]) -> std::io::Result<Vec<u8>> {
+    let mut cue = vec![];
+    cue.write_all(b"cue ")?;
+    cue.write_u32::<LittleEndian>(4 + 24 * cues.len() as u32)?;
+    cue.write_u32::<LittleEndian>(cues.len() as u32)?;
+    for (id, point) in (1..).zip(cues) {
+        /* name */        cue.write_u32::<LittleEndian>(id)?;
+        /* position */    cue.write_u32::<LittleEndian>(point.position)?;
+        /* chunk */       cue.write_all(b"data")?;
+        /* chunkStart */  cue.write_u32::<LittleEndian>(0)?;
+        /* blockStart */  cue.write_u32::<LittleEndian>(0)?;
+        /* sampleOffset */ cue.write_u32::<LittleEndian>(point.position)?;
+    }
+
+    let mut adtl = vec![];
+    adtl.write_all(b"adtl")?;
+    for (id, point) in (1u32..).zip(cues) {
+        let text_len = point.label.len() as u32 + 1;
+        adtl.write_all(b"labl")?;
+        adtl.write_u32::<LittleEndian>(4 + text_len)?;
+        adtl.write_u32::<LittleEndian>(id)?;
+        adtl.write_all(point.label.as_bytes())?;
+        adtl.write_all(&[0])?;
+        if text_len & 1 != 0 {
+            adtl.write_all(&[0])?;
+        }
+
+        if point.length > 0 {
+            adtl.write_all(b"ltxt")?;
+            adtl.write_u32::<LittleEndian>(20)?;
+            /* name */     adtl.write_u32::<LittleEndian>(id)?;
+            /* length */   adtl.write_u32::<LittleEndian>(point.length)?;
+            /* purpose */  adtl.write_all(b"rgn ")?;
+            /* country, language, dialect, code page */
+                           adtl.write_all(&[0; 8])?;
+        }
+    }
+
+    cue.write_all(b"LIST")?;
+    cue.write_u32::<LittleEndian>(adtl.len() as u32)?;
+    cue.write_all(&adtl)?;
+    Ok(cue)
+}
+
+/*
+      chunkId      : [0x52,0x49,0x46,0x46], // 0    4    "RIFF" = 0x52494646
+      chunkSize    : 0,                     // 4    4    36+SubChunk2Size = 4+(8+SubChunk1Size)+(8+SubChunk2Size)
+      format       : [0x57,0x41,0x56,0x45], // 8    4    "WAVE" = 0x57415645
+      subChunk1Id  : [0x66,0x6d,0x74,0x20], // 12   4    "fmt " = 0x666d7420
+      subChunk1Size: 16,                    // 16   4    16 for PCM
+      audioFormat  : 1,                     // 20   2    PCM = 1
+      numChannels  : options.channels,      // 22   2    Mono = 1, Stereo = 2...
+      sampleRate   : options.rate,          // 24   4    8000, 44100...
+      byteRate     : 0,                     // 28   4    SampleRate*NumChannels*BitsPerSample/8
+      blockAlign   : 0,                     // 32   2    NumChannels*BitsPerSample/8
+      bitsPerSample: options.depth,                     // 34   2    8 bits = 8, 16 bits = 16
+      subChunk2Id  : [0x64,0x61,0x74,0x61], // 36   4    "data" = 0x64617461
+      subChunk2Size: 0                      // 40   4    data size = NumSamples*NumChannels*BitsPerSample/8
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn le32(bytes: &[u8]) -> u32 {
+        u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16 | u32::from(bytes[3]) << 24
+    }
+
+    // Split a run of RIFF chunks into their ids and contents
+    fn chunks(mut bytes: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = vec![];
+        while bytes.len() >= 8 {
+            let mut id = [0; 4];
+            id.copy_from_slice(&bytes[..4]);
+            let len = le32(&bytes[4..8]) as usize;
+            chunks.push((id, &bytes[8..8 + len]));
+            bytes = &bytes[(8 + len + (len & 1)).min(bytes.len())..];
+        }
+        chunks
+    }
+
+    fn write(samples: &[f64], options: &WavOptions) -> Vec<u8> {
+        let mut file = vec![];
+        write_wav_to(8000, samples, options, &mut file).unwrap();
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(le32(&file[4..8]) as usize, file.len() - 8);
+        assert_eq!(&file[8..12], b"WAVE");
+        file
+    }
+
+    #[test]
+    fn sample_formats() {
+        let encode = |format: SampleFormat, sample: f64| {
+            let mut bytes = vec![];
+            format.encode(sample, &mut bytes);
+            bytes
+        };
+        assert_eq!(encode(SampleFormat::U8, 0.0), [0x80]);
+        assert_eq!(encode(SampleFormat::U8, 1.0), [0xff]);
+        assert_eq!(encode(SampleFormat::U8, -1.0), [0x01]);
+        assert_eq!(encode(SampleFormat::U8, -2.0), [0x00]);
+        assert_eq!(encode(SampleFormat::S16, 0.5), [0x00, 0x40]);
+        assert_eq!(encode(SampleFormat::S16, -1.0), [0x01, 0x80]);
+        assert_eq!(encode(SampleFormat::S16, 2.0), [0xff, 0x7f]);
+        assert_eq!(encode(SampleFormat::S24, 1.0), [0xff, 0xff, 0x7f]);
+        assert_eq!(encode(SampleFormat::S24, -0.5), [0x00, 0x00, 0xc0]);
+        assert_eq!(encode(SampleFormat::S32, -1.0), [0x01, 0x00, 0x00, 0x80]);
+        assert_eq!(encode(SampleFormat::F32, -0.25), [0x00, 0x00, 0x80, 0xbe]);
+
+        for (name, format) in SAMPLE_FORMAT_NAMES.iter().zip(&[
+            SampleFormat::U8,
+            SampleFormat::S16,
+            SampleFormat::S24,
+            SampleFormat::S32,
+            SampleFormat::F32,
+        ]) {
+            assert_eq!(SampleFormat::parse(name), Some(*format));
+            assert_eq!(encode(*format, 0.3).len(), format.bytes_per_sample() as usize);
+        }
+    }
+
+    #[test]
+    fn fmt_chunks() {
+        for &(format, extensible, fmt_len, tag, has_fact) in &[
+            (SampleFormat::S16, false, 16, FORMAT_PCM, false),
+            (SampleFormat::S24, false, 16, FORMAT_PCM, false),
+            (SampleFormat::F32, false, 18, FORMAT_IEEE_FLOAT, true),
+            (SampleFormat::S24, true, 40, FORMAT_EXTENSIBLE, false),
+            (SampleFormat::F32, true, 40, FORMAT_EXTENSIBLE, true),
+        ] {
+            let options = WavOptions {
+                format,
+                extensible,
+                ..WavOptions::default()
+            };
+            let file = write(&[0.0; 5], &options);
+            let chunks = chunks(&file[12..]);
+            let fmt = chunks[0].1;
+            assert_eq!(chunks[0].0, *b"fmt ");
+            assert_eq!(fmt.len(), fmt_len);
+            assert_eq!(u16::from(fmt[0]) | u16::from(fmt[1]) << 8, tag);
+            let bytes = format.bytes_per_sample();
+            assert_eq!(le32(&fmt[8..]), 8000 * bytes);
+            assert_eq!(u32::from(fmt[12]), bytes);
+            assert_eq!(u16::from(fmt[14]), format.bits_per_sample());
+            if fmt_len > 16 {
+                assert_eq!(usize::from(fmt[16]), fmt_len - 18);
+            }
+            if extensible {
+                assert_eq!(u16::from(fmt[18]), format.bits_per_sample());
+                assert_eq!(le32(&fmt[20..]), SPEAKER_FRONT_CENTER);
+                assert_eq!(u16::from(fmt[24]), format.format_tag());
+                assert_eq!(fmt[28..], SUBTYPE_GUID_TAIL);
+            }
+            assert_eq!(chunks[1].0 == *b"fact", has_fact);
+            if has_fact {
+                assert_eq!(le32(chunks[1].1), 5);
+            }
+            assert_eq!(chunks.last().unwrap().1.len(), 5 * bytes as usize);
+        }
+    }
+
+    #[test]
+    fn channel_layouts() {
+        for &(layout, ref expected) in &[
+            (ChannelLayout::Mono, vec![0.5, -0.25]),
+            (ChannelLayout::Left, vec![0.5, 0.0, -0.25, 0.0]),
+            (ChannelLayout::Both, vec![0.5, 0.5, -0.25, -0.25]),
+            (ChannelLayout::Differential, vec![0.5, -0.5, -0.25, 0.25]),
+        ] {
+            let options = WavOptions {
+                format: SampleFormat::F32,
+                layout,
+                extensible: true,
+                ..WavOptions::default()
+            };
+            let file = write(&[0.5, -0.25], &options);
+            let chunks = chunks(&file[12..]);
+            let fmt = chunks[0].1;
+            let channels = layout.channels();
+            assert_eq!(u16::from(fmt[2]), channels);
+            assert_eq!(le32(&fmt[8..]), 8000 * 4 * u32::from(channels));
+            assert_eq!(u16::from(fmt[12]), 4 * channels);
+            let mask = if channels == 1 { SPEAKER_FRONT_CENTER } else { SPEAKER_FRONT_LEFT_RIGHT };
+            assert_eq!(le32(&fmt[20..]), mask);
+            assert_eq!(le32(chunks[1].1), 2);
+
+            let data: Vec<f64> = chunks[2]
+                .1
+                .chunks(4)
+                .map(|b| f64::from(f32::from_bits(le32(b))))
+                .collect();
+            assert_eq!(&data, expected);
+        }
+
+        // Extra channels on a playback device stay silent
+        assert_eq!(ChannelLayout::Differential.channel_value(0.5, 2), 0.0);
+        assert_eq!(ChannelLayout::Left.channel_value(0.5, 1), 0.0);
+        assert_eq!(ChannelLayout::Both.channel_value(0.5, 5), 0.5);
+        for (name, layout) in CHANNEL_LAYOUT_NAMES.iter().zip(&[
+            ChannelLayout::Mono,
+            ChannelLayout::Left,
+            ChannelLayout::Both,
+            ChannelLayout::Differential,
+        ]) {
+            assert_eq!(ChannelLayout::parse(name), Some(*layout));
+        }
+    }
+
+    #[test]
+    fn info_chunk() {
+        let options = WavOptions {
+            info: vec![
+                (*b"INAM", "firmware.elf".to_owned()),
+                (*b"ICMT", "odd".to_owned()),
+                (*b"ISFT", String::new()),
+            ],
+            ..WavOptions::default()
+        };
+        let file = write(&[0.0; 3], &options);
+        let chunks = chunks(&file[12..]);
+        assert_eq!(chunks[1].0, *b"data");
+        assert_eq!(chunks[1].1.len(), 6);
+        assert_eq!(chunks[2].0, *b"LIST");
+        assert_eq!(chunks.len(), 3);
+
+        let list = chunks[2].1;
+        assert_eq!(&list[..4], b"INFO");
+        let info = self::chunks(&list[4..]);
+        assert_eq!(info[0].0, *b"INAM");
+        assert_eq!(info[0].1, b"firmware.elf\0");
+        assert_eq!(info[1].0, *b"ICMT");
+        assert_eq!(info[1].1, b"odd\0");
+        assert_eq!(info[2].0, *b"ISFT");
+        assert_eq!(info[2].1, b"\0");
+        // Each odd-length string is padded to keep the next one aligned
+        assert_eq!(list.len(), 4 + (8 + 14) + (8 + 4) + (8 + 2));
+    }
+
+    #[test]
+    fn streaming_and_raw() {
+        let options = WavOptions {
+            format: SampleFormat::U8,
+            streaming: true,
+            info: vec![(*b"INAM", "stream".to_owned())],
+            cues: vec![Cue { position: 1, length: 0, label: "Block 0".to_owned() }],
+            ..WavOptions::default()
+        };
+        let mut file = vec![];
+        write_wav_to(8000, &[0.0, 1.0, -1.0], &options, &mut file).unwrap();
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(le32(&file[4..]), STREAMING_LEN);
+        // The INFO text comes first, and the cue points are left out
+        assert_eq!(&file[36..40], b"LIST");
+        let data = 44 + le32(&file[40..]) as usize;
+        assert_eq!(&file[44..48], b"INFO");
+        assert_eq!(chunks(&file[48..data]), [(*b"INAM", &b"stream\0"[..])]);
+        assert_eq!(&file[data..data + 4], b"data");
+        assert_eq!(le32(&file[data + 4..]), STREAMING_LEN);
+        // Nothing follows the samples, not even a pad byte
+        assert_eq!(&file[data + 8..], [0x80, 0xff, 0x01]);
+
+        let float = WavOptions {
+            format: SampleFormat::F32,
+            streaming: true,
+            ..WavOptions::default()
+        };
+        let mut file = vec![];
+        write_wav_to(8000, &[0.0], &float, &mut file).unwrap();
+        assert_eq!(&file[38..42], b"fact");
+        assert_eq!(le32(&file[46..]), STREAMING_LEN);
+        assert_eq!(le32(&file[54..]), STREAMING_LEN);
+
+        let raw_options = WavOptions {
+            format: SampleFormat::S16,
+            layout: ChannelLayout::Differential,
+            ..options
+        };
+        let mut raw = vec![];
+        write_raw_to(&[0.5, -1.0], &raw_options, &mut raw).unwrap();
+        assert_eq!(raw, [0x00, 0x40, 0x00, 0xc0, 0x01, 0x80, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn cue_chunks() {
+        let options = WavOptions {
+            cues: vec![
+                Cue { position: 0, length: 0, label: "Start".to_owned() },
+                Cue { position: 3, length: 4, label: "Block 0".to_owned() },
+            ],
+            ..WavOptions::default()
+        };
+        let file = write(&[0.0; 9], &options);
+        let chunks = chunks(&file[12..]);
+        let ids: Vec<&[u8]> = chunks.iter().map(|c| &c.0[..]).collect();
+        assert_eq!(ids, [&b"fmt "[..], b"data", b"cue ", b"LIST"]);
+
+        let cue = chunks[2].1;
+        assert_eq!(le32(cue), 2);
+        let points: Vec<&[u8]> = cue[4..].chunks(24).collect();
+        assert_eq!(le32(points[1]), 2);
+        assert_eq!(le32(&points[1][4..]), 3);
+        assert_eq!(&points[1][8..12], b"data");
+        assert_eq!(le32(&points[1][20..]), 3);
+
+        let list = chunks[3].1;
+        assert_eq!(&list[..4], b"adtl");
+        let adtl = self::chunks(&list[4..]);
+        assert_eq!(adtl[0].0, *b"labl");
+        assert_eq!(adtl[0].1, b"\x01\0\0\0Start\0");
+        assert_eq!(adtl[1].0, *b"labl");
+        assert_eq!(adtl[1].1, b"\x02\0\0\0Block 0\0");
+        assert_eq!(adtl[2].0, *b"ltxt");
+        assert_eq!(le32(&adtl[2].1[4..]), 4);
+        assert_eq!(&adtl[2].1[8..12], b"rgn ");
+        assert_eq!(adtl.len(), 3);
+    }
+
+    fn pieces(samples: &[f64], options: &WavOptions, cues: Vec<Cue>) -> Vec<u8> {
+        let mut writer = WavWriter::new(std::io::Cursor::new(vec![]), 8000, options).unwrap();
+        for piece in samples.chunks(4) {
+            writer.write_samples(piece).unwrap();
+        }
+        writer.set_cues(cues);
+        writer.finalize().unwrap().into_inner()
+    }
+
+    #[test]
+    fn writer_matches_whole() {
+        let samples: Vec<f64> = (0..11).map(|n| n as f64 / 11.0 - 0.5).collect();
+        let cues = vec![Cue { position: 2, length: 5, label: "Block 0".to_owned() }];
+        for &format in &[SampleFormat::U8, SampleFormat::S16, SampleFormat::F32] {
+            let options = WavOptions {
+                format,
+                info: vec![(*b"INAM", "test".to_owned())],
+                ..WavOptions::default()
+            };
+            let whole = write(
+                &samples,
+                &WavOptions {
+                    cues: cues.clone(),
+                    ..options.clone()
+                },
+            );
+            assert_eq!(pieces(&samples, &options, cues.clone()), whole);
+        }
+    }
+
+    #[test]
+    fn writer_keeps_room_for_rf64() {
+        let options = WavOptions {
+            rf64: true,
+            ..WavOptions::default()
+        };
+        let file = pieces(&[0.25; 9], &options, vec![]);
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(le32(&file[4..8]) as usize, file.len() - 8);
+        let chunks = chunks(&file[12..]);
+        assert_eq!(chunks[0].0, *b"JUNK");
+        assert_eq!(chunks[0].1.len(), DS64_LEN as usize);
+        assert_eq!(chunks[2].0, *b"data");
+        assert_eq!(chunks[2].1.len(), 18);
+    }
+
+    #[test]
+    fn writer_streams_without_seeking() {
+        let options = WavOptions {
+            streaming: true,
+            cues: vec![Cue { position: 0, length: 0, label: "Start".to_owned() }],
+            ..WavOptions::default()
+        };
+        let stream = |options: &WavOptions| {
+            let mut writer = WavWriter::new(vec![], 8000, options).unwrap();
+            writer.write_samples(&[0.5; 3]).unwrap();
+            writer.write_samples(&[-0.5; 2]).unwrap();
+            let file = writer.finish_stream().unwrap();
+
+            let mut whole = vec![];
+            write_wav_to(8000, &[0.5, 0.5, 0.5, -0.5, -0.5], options, &mut whole).unwrap();
+            assert_eq!(file, whole);
+            assert_eq!(le32(&file[4..8]), STREAMING_LEN);
+            file
+        };
+        assert_eq!(stream(&options).len(), 44 + 10);
+
+        // INFO text is written with the header, ahead of the samples
+        let file = stream(&WavOptions {
+            info: vec![(*b"INAM", "firmware.elf".to_owned()), (*b"ISFT", "ltc".to_owned())],
+            ..options
+        });
+        let list = self::chunks(&file[36..file.len() - 18]);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].0, *b"LIST");
+        assert_eq!(&list[0].1[..4], b"INFO");
+        assert_eq!(
+            self::chunks(&list[0].1[4..]),
+            [(*b"INAM", &b"firmware.elf\0"[..]), (*b"ISFT", &b"ltc\0"[..])]
+        );
+        assert_eq!(&file[file.len() - 18..file.len() - 14], b"data");
+
+        let writer = WavWriter::new(vec![], 8000, &WavOptions::default()).unwrap();
+        assert!(writer.finish_stream().is_err());
+    }
+}